@@ -1,13 +1,25 @@
+use crate::bookmarks::Bookmarks;
+use crate::columns::Columns;
+use crate::constants::{DOUBLE_CLICK, SPEED_STEP, VOLUME_STEP};
 use crate::directories::Directories;
 use crate::directories::State;
 use crate::files::Files;
-use crate::switch_play_mode;
+use crate::keymap::{Action, Context, Keymap, Mode, Preset, Resolved};
+use crate::theme::{self, Theme};
+use crate::utils::settings::Settings;
 use crate::KeyCode;
 use crate::KeyModifiers;
-use crossterm::event::Event;
-use crossterm::event::KeyEvent;
+use crossterm::event::{Event, KeyEvent, MouseButton, MouseEvent, MouseEventKind};
+use itertools::Itertools;
+use ratatui::layout::{Position, Rect};
 use ratatui::Frame;
+use std::cmp;
+use std::collections::HashSet;
 use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
+use strum::IntoEnumIterator;
+use sublime_fuzzy::{FuzzySearch, Scoring};
 
 pub type Chord = (KeyModifiers, KeyCode);
 
@@ -31,86 +43,565 @@ pub fn get_path_completions(path: &String) -> Vec<String> {
 pub enum Tab {
     Directories,
     Files,
+    Bookmarks,
+}
+
+/// Password typed for an encrypted archive.
+pub struct PasswordPrompt {
+    pub archive: PathBuf,
+    /// The last password was wrong
+    pub wrong: bool,
+    pub password: String,
+}
+
+/// Keys of the tab under the help, filtered by what is typed.
+pub struct Help {
+    pub contexts: &'static [Context],
+    pub filter: String,
+    pub scroll: usize,
+}
+
+impl Help {
+    /// Rows of keys, description and context, matching the filter.
+    pub fn lines(&self, keymap: &Keymap) -> Vec<[String; 3]> {
+        let filter = self.filter.to_lowercase();
+        keymap
+            .active(self.contexts)
+            .into_iter()
+            .map(|(context, keys, action)| {
+                [keys, action.description().to_string(), context.to_string()]
+            })
+            .filter(|line| {
+                line.iter()
+                    .any(|column| column.to_lowercase().contains(&filter))
+            })
+            .collect()
+    }
+}
+
+/// Actions searched by description, run on the tab underneath.
+pub struct Palette {
+    pub contexts: &'static [Context],
+    pub filter: String,
+    pub line_index: usize,
+}
+
+pub struct PaletteLine {
+    pub action: Action,
+    /// Keys of the action in the tab underneath
+    pub keys: String,
+    /// Matched characters of the description
+    pub indices: Vec<usize>,
+}
+
+impl Palette {
+    /// Actions matching the filter, the best first.
+    pub fn lines(&self, keymap: &Keymap, scoring: &Scoring) -> Vec<PaletteLine> {
+        let active = keymap.active(self.contexts);
+        Action::iter()
+            .filter(|action| *action != Action::CommandPalette)
+            .filter_map(|action| {
+                let (score, indices) = match self.filter.is_empty() {
+                    true => (0, vec![]),
+                    false => {
+                        let matched = FuzzySearch::new(&self.filter, action.description())
+                            .case_insensitive()
+                            .score_with(scoring)
+                            .best_match()
+                            .filter(|matched| matched.score() > 0)?;
+                        (
+                            matched.score(),
+                            matched.matched_indices().copied().collect_vec(),
+                        )
+                    }
+                };
+                let keys = active
+                    .iter()
+                    .filter(|(_, _, bound)| *bound == action)
+                    .map(|(_, keys, _)| keys)
+                    .join(", ");
+                Some((
+                    score,
+                    PaletteLine {
+                        action,
+                        keys,
+                        indices,
+                    },
+                ))
+            })
+            .sorted_by_key(|(score, _)| cmp::Reverse(*score))
+            .map(|(_, line)| line)
+            .collect()
+    }
 }
 
 pub struct App<'a> {
     pub current_place: Tab,
     pub directories: Directories<'a>,
     pub files: Files,
+    pub bookmarks: Bookmarks,
+    pub password_prompt: Option<PasswordPrompt>,
+    // Archives whose prompt was closed without a password
+    dismissed_archives: HashSet<PathBuf>,
+    pub settings: Settings,
+    // Why the edited config file was not applied
+    pub config_error: Option<String>,
+    pub keymap: Keymap,
+    pub theme: Theme,
+    pub columns: Columns,
+    // With the vim preset
+    pub files_mode: Mode,
+    pub help: Option<Help>,
+    pub palette: Option<Palette>,
+    // Time and line of the last click in Files
+    last_click: Option<(Instant, usize)>,
 }
 
 impl<'a> App<'a> {
-    pub fn new() -> App<'a> {
-        let mut files = Files::new();
-        let directories = Directories::new();
+    pub fn new(settings: Settings) -> App<'a> {
+        let mut files = Files::new(settings.clone());
+        let directories = Directories::new(settings.clone());
         files.update_paths(&directories.working_directories);
         files.update_lines();
         files.update_lines_filtered();
         files.watch_archives();
+        files.listen_start();
         files.current_file = String::default();
         files.play_file();
 
+        let config = settings.config();
+        let keymap = Keymap::new(config.keymap_preset, &config.keybindings);
+        let theme = Theme::new(&config.theme, &config.themes, theme::no_color());
+        let columns = Columns::new(&config.columns, &config.date_format);
+        let config_error = config_errors(&keymap, &theme, &columns);
+
         App {
             directories,
             files,
+            bookmarks: Bookmarks::new(),
             current_place: Tab::Directories,
+            password_prompt: None,
+            dismissed_archives: HashSet::new(),
+            keymap,
+            theme,
+            columns,
+            files_mode: Mode::Normal,
+            help: None,
+            palette: None,
+            last_click: None,
+            settings,
+            config_error,
         }
     }
 
     pub fn handle_tick(&mut self) {
         self.files.on_tick();
+        if self.files.take_config_changed() {
+            self.reload_config();
+        }
+        self.settings.save_settled();
+        if self.password_prompt.is_none() {
+            self.open_password_prompt();
+        }
+    }
+
+    /// Apply the config file edited while playing, or report why it is not.
+    fn reload_config(&mut self) {
+        match self.settings.reload() {
+            Ok(true) => {
+                log::info!("Config reloaded");
+                let config = self.settings.config();
+                self.files.apply_config(&config);
+                self.directories.working_directories = config.working_directories.clone();
+                self.directories.line_index = 0;
+                self.files.update_paths(&config.working_directories);
+                self.files.update_lines();
+                self.files.update_lines_filtered();
+                self.keymap = Keymap::new(config.keymap_preset, &config.keybindings);
+                self.theme = Theme::new(&config.theme, &config.themes, theme::no_color());
+                self.columns = Columns::new(&config.columns, &config.date_format);
+                self.files_mode = Mode::Normal;
+                self.help = None;
+                self.palette = None;
+                self.config_error = config_errors(&self.keymap, &self.theme, &self.columns);
+            }
+            Ok(false) => {}
+            Err(e) => {
+                log::error!("{e}");
+                self.config_error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Ask for the password of the next encrypted archive.
+    fn open_password_prompt(&mut self) {
+        let status = self.files.archive_status.read().unwrap();
+        self.password_prompt = status
+            .password_requests
+            .iter()
+            .find(|request| !self.dismissed_archives.contains(&request.archive))
+            .map(|request| PasswordPrompt {
+                archive: request.archive.clone(),
+                wrong: request.wrong,
+                password: String::default(),
+            });
+    }
+
+    fn handle_event_password(&mut self, chord: Chord) {
+        let Some(prompt) = &mut self.password_prompt else {
+            return;
+        };
+        match chord {
+            (_, KeyCode::Esc) => {
+                self.dismissed_archives.insert(prompt.archive.clone());
+                self.password_prompt = None;
+            }
+            (_, KeyCode::Enter) => {
+                self.files.unlock_archive(&prompt.archive, &prompt.password);
+                self.password_prompt = None;
+            }
+            (_, KeyCode::Backspace) => {
+                prompt.password.pop();
+            }
+            (_, KeyCode::Char(c)) => prompt.password.push(c),
+            _ => {}
+        }
+    }
+
+    fn toggle_help(&mut self) {
+        self.palette = None;
+        self.help = match self.help {
+            Some(_) => None,
+            None => Some(Help {
+                contexts: self.contexts(),
+                filter: String::default(),
+                scroll: 0,
+            }),
+        };
+    }
+
+    fn toggle_palette(&mut self) {
+        self.help = None;
+        self.palette = match self.palette {
+            Some(_) => None,
+            None => Some(Palette {
+                contexts: self.contexts(),
+                filter: String::default(),
+                line_index: 0,
+            }),
+        };
+    }
+
+    /// The action picked, if any.
+    fn handle_event_palette(&mut self, chord: Chord) -> Option<Action> {
+        let palette = self.palette.as_mut()?;
+        let lines = palette.lines(&self.keymap, &self.files.scoring);
+        match chord {
+            (_, KeyCode::Esc) => self.palette = None,
+            (_, KeyCode::Enter) => {
+                let action = lines.get(palette.line_index).map(|line| line.action);
+                self.palette = None;
+                return action;
+            }
+            (_, KeyCode::Up) => palette.line_index = palette.line_index.saturating_sub(1),
+            (_, KeyCode::Down) => {
+                palette.line_index = cmp::min(palette.line_index + 1, lines.len().saturating_sub(1))
+            }
+            (_, KeyCode::Backspace) => {
+                palette.filter.pop();
+                palette.line_index = 0;
+            }
+            (_, KeyCode::Char(c)) => {
+                palette.filter.push(c);
+                palette.line_index = 0;
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn handle_event_help(&mut self, frame: &Frame, chord: Chord) {
+        let Some(help) = &mut self.help else {
+            return;
+        };
+        let lines = help.lines(&self.keymap).len();
+        let height = crate::ui::help_area(crate::ui::get_chunks(frame)[1]).height as usize;
+        let page = height.saturating_sub(2);
+        let last = lines.saturating_sub(page);
+        match chord {
+            (_, KeyCode::Esc) => self.help = None,
+            (_, KeyCode::Up) => help.scroll = help.scroll.saturating_sub(1),
+            (_, KeyCode::Down) => help.scroll = cmp::min(help.scroll + 1, last),
+            (_, KeyCode::PageUp) => help.scroll = help.scroll.saturating_sub(page),
+            (_, KeyCode::PageDown) => help.scroll = cmp::min(help.scroll + page, last),
+            (_, KeyCode::Backspace) => {
+                help.filter.pop();
+                help.scroll = 0;
+            }
+            (_, KeyCode::Char(c)) => {
+                help.filter.push(c);
+                help.scroll = 0;
+            }
+            _ => {}
+        }
     }
 
     pub fn cycle_tab(&mut self) {
         self.current_place = match self.current_place {
             Tab::Directories => Tab::Files,
-            Tab::Files => Tab::Directories,
+            Tab::Files => Tab::Bookmarks,
+            Tab::Bookmarks => Tab::Directories,
         };
     }
 
-    pub fn handle_event_movement(&mut self, frame: &Frame, chord: Chord) {
-        match &self.current_place {
-            Tab::Directories => match chord {
-                (_, KeyCode::Tab) => match self.directories.state {
-                    State::Base => self.cycle_tab(),
-                    State::Editing => self.directories.handle_event(chord),
-                },
-                _ => self.directories.handle_event(chord),
-            },
-            Tab::Files => match chord {
-                (_, KeyCode::Tab) => self.cycle_tab(),
-                (KeyModifiers::CONTROL, KeyCode::Char('t')) => switch_play_mode(),
-                _ => self.files.handle_event(frame, chord),
+    /// Contexts of the keys, from the most specific one.
+    fn contexts(&self) -> &'static [Context] {
+        match self.current_place {
+            // Keys are typed in
+            _ if self.help.is_some() || self.palette.is_some() => &[Context::Global],
+            _ if self.password_prompt.is_some() => &[Context::Global],
+            Tab::Directories if self.directories.state == State::Editing => &[Context::Global],
+            Tab::Directories => &[Context::Directories, Context::Global],
+            Tab::Files => match (self.keymap.preset, self.files_mode) {
+                (Preset::Default, _) => &[Context::Files, Context::Playback, Context::Global],
+                (Preset::Vim, Mode::Normal) => &[
+                    Context::Normal,
+                    Context::Files,
+                    Context::Playback,
+                    Context::Global,
+                ],
+                (Preset::Vim, Mode::Filter) => &[Context::Filter, Context::Global],
             },
+            Tab::Bookmarks => &[Context::Bookmarks, Context::Playback, Context::Global],
         }
     }
 
-    pub fn handle_event(&mut self, frame: &Frame, event: &Event) -> Result<(), ()> {
-        let &Event::Key(
-            KeyEvent {
-                code, modifiers, ..
+    fn handle_action(&mut self, frame: &Frame, action: Action) {
+        match (self.current_place, action) {
+            (_, Action::Quit) => {}
+            (_, Action::Help) => self.toggle_help(),
+            (_, Action::CommandPalette) => self.toggle_palette(),
+            (_, Action::AddDirectory) => {
+                self.current_place = Tab::Directories;
+                self.directories.start_adding();
+            }
+            (_, Action::ClearCache) => self.files.clear_archive_cache(),
+            (_, Action::CycleSort) => self.files.cycle_sort(),
+            (_, Action::JumpToPlaying) => {
+                self.current_place = Tab::Files;
+                self.files.jump_to_playing();
+            }
+            (_, Action::NextTab) => self.cycle_tab(),
+            (_, Action::SwitchPlayMode) => self.files.switch_play_mode(),
+            (_, Action::CycleOutputDevice) => self.files.cycle_output_device(),
+            (_, Action::SpeedUp) => self.files.set_speed(self.files.speed + SPEED_STEP),
+            (_, Action::SpeedDown) => self.files.set_speed(self.files.speed - SPEED_STEP),
+            (_, Action::SaveSpeed) => self.files.save_speed(),
+            (_, Action::VolumeUp) => self.files.set_volume(self.files.volume + VOLUME_STEP),
+            (_, Action::VolumeDown) => self.files.set_volume(self.files.volume - VOLUME_STEP),
+            (_, Action::SetLoopA) => self.files.set_loop_a(),
+            (_, Action::SetLoopB) => self.files.set_loop_b(),
+            (_, Action::ClearLoop) => self.files.clear_loop(),
+            (_, Action::PlayNext) => self.files.play_next(),
+            (_, Action::PlayPrevious) => self.files.play_previous(),
+            (_, Action::TogglePause) => self.files.toggle_pause(),
+            (_, Action::StartFilter) => self.files_mode = Mode::Filter,
+            (_, Action::StopFilter) => self.files_mode = Mode::Normal,
+            (Tab::Directories, Action::Up) => self.directories.line_up(),
+            (Tab::Directories, Action::Down) => self.directories.line_down(),
+            (Tab::Directories, Action::Delete) => self.directories.remove_line(),
+            (Tab::Directories, Action::ToggleDirectory) => self.directories.toggle_line(),
+            (Tab::Files, Action::Up) => self.files.lines_up(1),
+            (Tab::Files, Action::Down) => self.files.lines_down(1),
+            (Tab::Files, Action::PageUp) => self.files.page_up(frame),
+            (Tab::Files, Action::PageDown) => self.files.page_down(frame),
+            (Tab::Files, Action::Select) => self.files.play_file(),
+            (Tab::Files, Action::FirstLine) => self.files.first_line(),
+            (Tab::Files, Action::LastLine) => self.files.last_line(),
+            (Tab::Bookmarks, Action::Up) => self.bookmarks.line_up(),
+            (Tab::Bookmarks, Action::Down) => self.bookmarks.line_down(&self.files),
+            (Tab::Bookmarks, Action::Select) => self.bookmarks.select(&mut self.files),
+            (Tab::Bookmarks, Action::Delete) => self.bookmarks.remove_line(&self.files),
+            _ => {}
+        }
+    }
+
+    fn handle_mouse(&mut self, frame: &Frame, mouse: MouseEvent) {
+        // Prompts and popups are kept to the keyboard
+        if self.password_prompt.is_some() || self.help.is_some() || self.palette.is_some() {
+            return;
+        }
+        let chunks = crate::ui::get_chunks(frame);
+        let position = Position::new(mouse.column, mouse.row);
+        let list = chunks[1];
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) if chunks[0].contains(position) => {
+                if let Some(tab) = crate::ui::tab_at(chunks[0], mouse.column) {
+                    self.current_place = tab;
+                }
+            }
+            MouseEventKind::Down(MouseButton::Left) if list.contains(position) => {
+                self.click_line(list, mouse.row)
+            }
+            MouseEventKind::Down(MouseButton::Left) => {
+                let progress = crate::ui::now_playing_rows(chunks[3])[1];
+                if progress.contains(position) {
+                    self.click_progress(progress, mouse.column);
+                }
+            }
+            MouseEventKind::ScrollDown if list.contains(position) => match self.current_place {
+                Tab::Directories => self.directories.line_down(),
+                Tab::Files => self.files.lines_down(1),
+                Tab::Bookmarks => self.bookmarks.line_down(&self.files),
             },
-            ..,
-        ) = event
-        else {
-            return Err(());
+            MouseEventKind::ScrollUp if list.contains(position) => match self.current_place {
+                Tab::Directories => self.directories.line_up(),
+                Tab::Files => self.files.lines_up(1),
+                Tab::Bookmarks => self.bookmarks.line_up(),
+            },
+            _ => {}
+        }
+    }
+
+    /// Select the line at `row` of `list`, play it on a second click.
+    fn click_line(&mut self, list: Rect, row: u16) {
+        match self.current_place {
+            Tab::Directories => {
+                // Under the top border, the candidate line last
+                let Some(index) = (row - list.y).checked_sub(1) else {
+                    return;
+                };
+                let index = index as usize;
+                if index < self.directories.working_directories.len() {
+                    self.directories.state = State::Base;
+                    self.directories.line_index = index as i32;
+                } else if index == self.directories.working_directories.len() {
+                    self.directories.start_adding();
+                }
+            }
+            Tab::Files => {
+                let (first, _, end) = self.files.visible_lines(list.height);
+                let index = first + (row - list.y) as usize;
+                if index >= end {
+                    return;
+                }
+                self.files.line_index = index as i32;
+                let double = self
+                    .last_click
+                    .is_some_and(|(time, line)| line == index && time.elapsed() < DOUBLE_CLICK);
+                match double {
+                    true => {
+                        self.last_click = None;
+                        self.files.play_file();
+                    }
+                    false => self.last_click = Some((Instant::now(), index)),
+                }
+            }
+            Tab::Bookmarks => {}
+        }
+    }
+
+    /// Seek to the clicked part of the song, when its duration is known.
+    fn click_progress(&mut self, progress: Rect, column: u16) {
+        let Some(duration) = self.files.current_tags().duration.filter(|d| !d.is_zero()) else {
+            return;
         };
+        let label = crate::ui::progress_label(self.files.position(), Some(duration));
+        if let Some(ratio) = crate::ui::seek_ratio(progress, &label, column) {
+            self.files.seek(duration.mul_f64(ratio));
+        }
+    }
 
-        let chord = (modifiers, code);
+    /// Keys bound to no action, typed in.
+    fn handle_input(&mut self, chord: Chord) {
+        if self.password_prompt.is_some() {
+            self.handle_event_password(chord);
+            return;
+        }
+        match self.current_place {
+            Tab::Directories => self.directories.handle_event(chord),
+            // Letters are shortcuts out of the filter
+            Tab::Files if self.keymap.preset == Preset::Vim && self.files_mode == Mode::Normal => {}
+            Tab::Files => self.files.handle_event(chord),
+            Tab::Bookmarks => self.bookmarks.handle_event(chord),
+        }
+    }
 
-        if chord == (KeyModifiers::CONTROL, KeyCode::Char('c'))
-            || chord == (KeyModifiers::CONTROL, KeyCode::Char('d'))
-        {
-            return Err(());
+    /// Chords bound to nothing go to the palette, the help or the text input.
+    fn handle_unbound(&mut self, frame: &Frame, chord: Chord) -> Option<Action> {
+        if self.palette.is_some() {
+            return self.handle_event_palette(chord);
         }
+        match self.help.is_some() {
+            true => self.handle_event_help(frame, chord),
+            false => self.handle_input(chord),
+        }
+        None
+    }
 
-        self.handle_event_movement(frame, chord);
+    pub fn handle_event(&mut self, frame: &Frame, event: &Event) -> Result<(), ()> {
+        let chord = match *event {
+            Event::Key(KeyEvent {
+                code, modifiers, ..
+            }) => (modifiers, code),
+            Event::Mouse(mouse) => {
+                self.handle_mouse(frame, mouse);
+                return Ok(());
+            }
+            // Laid out again when redrawn, clicks before it were elsewhere
+            Event::Resize(..) => {
+                self.last_click = None;
+                return Ok(());
+            }
+            // Redrawn by the caller
+            _ => return Ok(()),
+        };
+
+        let working_directories = self.directories.working_directories.clone();
+        let (unconsumed, resolved) = self.keymap.resolve(self.contexts(), chord);
+        let mut actions = unconsumed
+            .into_iter()
+            .filter_map(|chord| self.handle_unbound(frame, chord))
+            .collect_vec();
+        actions.extend(match resolved {
+            Resolved::Action(action) => Some(action),
+            Resolved::Pending => None,
+            Resolved::Unbound => self.handle_unbound(frame, chord),
+        });
+        for action in actions {
+            match action {
+                Action::Quit => return Err(()),
+                action => self.handle_action(frame, action),
+            }
+        }
+        // Follow the working paths
+        if self.directories.working_directories != working_directories {
+            self.files
+                .update_paths(&self.directories.working_directories);
+            self.files.update_lines();
+            self.files.update_lines_filtered();
+        }
         Result::Ok(())
     }
 }
 
-impl<'a> Default for App<'a> {
-    fn default() -> Self {
-        Self::new()
+fn config_errors(keymap: &Keymap, theme: &Theme, columns: &Columns) -> Option<String> {
+    let errors = [
+        ("Keybindings", &keymap.errors),
+        ("Theme", &theme.errors),
+        ("Columns", &columns.errors),
+    ]
+    .into_iter()
+    .filter(|(_, errors)| !errors.is_empty())
+    .map(|(part, errors)| {
+        for error in errors {
+            log::error!("{part}: {error}");
+        }
+        format!("{part}: {}", errors.join(", "))
+    })
+    .collect_vec();
+    match errors.is_empty() {
+        true => None,
+        false => Some(errors.join(" ")),
     }
 }
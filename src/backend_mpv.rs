@@ -1,6 +1,7 @@
 use crate::backend_trait::AudioBackend;
 use crate::constants::SongState;
 use libmpv2::Mpv as MpvBackend;
+use std::time::Duration;
 
 // http://mpv.io/manual/master/#options
 // http://mpv.io/manual/master/#list-of-input-commands
@@ -14,15 +15,30 @@ pub struct Mpv {
 
 impl Mpv {
     // pub fn new(echo_area_sender: Sender<String>) -> Self {
-    pub fn new() -> Self {
+    pub fn new(device: Option<&str>) -> Self {
         let mpv = MpvBackend::new().expect("Couldn't initialize MpvHandlerBuilder");
         mpv.set_property("vo", "null")
             .expect("Couldn't set vo=null in libmpv");
-        Self {
+        // scaletempo when the speed is changed
+        mpv.set_property("audio-pitch-correction", true)
+            .expect("Couldn't set audio-pitch-correction=yes in libmpv");
+        let mut mpv = Self {
             player: mpv,
             file_name: String::default(),
             // echo_area_sender,
-        }
+        };
+        mpv.set_device(device);
+        mpv
+    }
+}
+
+// Devices are listed by their alsa name, mpv prefixes them with the audio output.
+// https://mpv.io/manual/master/#options-audio-device
+fn audio_device(device: Option<&str>) -> String {
+    match device {
+        None => String::from("auto"),
+        Some(device) if device.contains('/') => String::from(device),
+        Some(device) => format!("alsa/{device}"),
     }
 }
 
@@ -97,6 +113,57 @@ impl AudioBackend for Mpv {
     fn file_name(&self) -> String {
         self.file_name.clone()
     }
+
+    fn set_device(&mut self, device: Option<&str>) {
+        let audio_device = audio_device(device);
+        if let Err(e) = self
+            .player
+            .set_property("audio-device", audio_device.as_str())
+        {
+            log::error!("Could not set mpv audio-device {audio_device}: {e}");
+        }
+    }
+
+    fn set_speed(&mut self, speed: f32) {
+        if let Err(e) = self.player.set_property("speed", speed as f64) {
+            log::error!("Could not set mpv speed {speed}: {e}");
+        }
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        if let Err(e) = self.player.set_property("volume", volume as f64 * 100.0) {
+            log::error!("Could not set mpv volume {volume}: {e}");
+        }
+    }
+
+    fn position(&self) -> Duration {
+        let time_pos = self.player.get_property::<f64>("time-pos").unwrap_or(0.0);
+        Duration::from_secs_f64(time_pos.max(0.0))
+    }
+
+    fn seek(&mut self, position: Duration) {
+        if let Err(e) = self.player.command(
+            "seek",
+            &[&format!("{}", position.as_secs_f64()), "absolute"],
+        ) {
+            log::error!("Could not seek to {position:?}: {e}");
+        }
+    }
+
+    fn set_loop(&mut self, ab_loop: Option<(Duration, Duration)>) {
+        let (a, b) = match ab_loop {
+            Some((a, b)) => (
+                format!("{}", a.as_secs_f64()),
+                format!("{}", b.as_secs_f64()),
+            ),
+            None => (String::from("no"), String::from("no")),
+        };
+        for (property, value) in [("ab-loop-a", a), ("ab-loop-b", b)] {
+            if let Err(e) = self.player.set_property(property, value.as_str()) {
+                log::error!("Could not set mpv {property} {value}: {e}");
+            }
+        }
+    }
 }
 
 impl Mpv {
@@ -0,0 +1,223 @@
+use crate::backend_trait::AudioBackend;
+use crate::constants::SongState;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+// Plays nothing, but keeps track of time and durations so that songs end.
+// With a manual clock, time only moves when `advance` is called, which makes
+// queue / autoplay behavior testable without a sound card.
+
+const DEFAULT_DURATION: Duration = Duration::from_secs(180);
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Call {
+    Stop,
+    Start(String),
+    Pause,
+    SilentPause,
+    Resume,
+    Toggle,
+    SetDevice(Option<String>),
+    SetSpeed(f32),
+    SetVolume(f32),
+    Seek(Duration),
+    SetLoop(Option<(Duration, Duration)>),
+}
+
+struct Inner {
+    state: SongState,
+    file_name: String,
+    position: Duration,
+    speed: f32,
+    ab_loop: Option<(Duration, Duration)>,
+    // None for a manual clock
+    last_sync: Option<Instant>,
+    durations: HashMap<String, Duration>,
+    calls: Vec<Call>,
+}
+
+/// Clones share the same clock and call log.
+#[derive(Clone)]
+pub struct Null {
+    inner: Arc<RwLock<Inner>>,
+}
+
+impl Null {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Inner {
+                state: SongState::Ended,
+                file_name: String::default(),
+                position: Duration::ZERO,
+                speed: 1.0,
+                ab_loop: None,
+                last_sync: Some(Instant::now()),
+                durations: HashMap::new(),
+                calls: vec![],
+            })),
+        }
+    }
+
+    /// Time does not flow by itself, see `advance`.
+    #[cfg(test)]
+    pub fn manual() -> Self {
+        let null = Self::new();
+        null.inner.write().unwrap().last_sync = None;
+        null
+    }
+
+    /// Duration reported for `file_name`, defaults to three minutes.
+    #[cfg(test)]
+    pub fn set_duration(&self, file_name: &str, duration: Duration) {
+        let mut inner = self.inner.write().unwrap();
+        inner.durations.insert(file_name.to_string(), duration);
+    }
+
+    pub fn duration(&self, file_name: &str) -> Duration {
+        let inner = self.inner.read().unwrap();
+        *inner.durations.get(file_name).unwrap_or(&DEFAULT_DURATION)
+    }
+
+    /// Move the clock forward, songs move `speed` times faster.
+    /// The current song ends when its duration is reached.
+    pub fn advance(&self, elapsed: Duration) {
+        let duration = self.duration(&self.file_name());
+        let mut inner = self.inner.write().unwrap();
+        if inner.state != SongState::Playing {
+            return;
+        }
+        inner.position = (inner.position + elapsed.mul_f32(inner.speed)).min(duration);
+        if let Some((a, b)) = inner.ab_loop {
+            if inner.position >= b {
+                inner.position = a;
+            }
+        }
+        if inner.position == duration {
+            inner.state = SongState::Ended;
+        }
+    }
+
+    /// Catch up with the wall clock, unless the clock is manual.
+    fn sync(&self) {
+        let Some(last_sync) = self.inner.read().unwrap().last_sync else {
+            return;
+        };
+        let now = Instant::now();
+        self.advance(now - last_sync);
+        self.inner.write().unwrap().last_sync = Some(now);
+    }
+
+    #[cfg(test)]
+    pub fn calls(&self) -> Vec<Call> {
+        self.inner.read().unwrap().calls.clone()
+    }
+
+    #[cfg(test)]
+    pub fn clear_calls(&self) {
+        self.inner.write().unwrap().calls.clear();
+    }
+
+    fn record(&self, call: Call) {
+        log::debug!("null backend: {:?}", call);
+        self.inner.write().unwrap().calls.push(call);
+    }
+
+    fn set_state(&self, state: SongState) {
+        self.sync();
+        self.inner.write().unwrap().state = state;
+    }
+}
+
+impl Default for Null {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioBackend for Null {
+    fn stop(&mut self) {
+        self.record(Call::Stop);
+        self.set_state(SongState::Ended);
+    }
+
+    fn start(&mut self, file_name: &str) {
+        self.record(Call::Start(file_name.to_string()));
+        self.sync();
+        let mut inner = self.inner.write().unwrap();
+        inner.file_name = file_name.to_string();
+        inner.position = Duration::ZERO;
+        inner.state = SongState::Playing;
+    }
+
+    fn pause(&mut self) {
+        self.record(Call::Pause);
+        self.set_state(SongState::Paused);
+    }
+
+    fn silent_pause(&mut self) {
+        self.record(Call::SilentPause);
+        self.set_state(SongState::Paused);
+    }
+
+    fn resume(&mut self) {
+        self.record(Call::Resume);
+        self.set_state(SongState::Playing);
+    }
+
+    fn busy(&self) -> bool {
+        self.state() != SongState::Ended
+    }
+
+    fn state(&self) -> SongState {
+        self.sync();
+        self.inner.read().unwrap().state
+    }
+
+    fn toggle(&mut self) {
+        self.record(Call::Toggle);
+        match self.state() {
+            SongState::Paused => self.set_state(SongState::Playing),
+            SongState::Playing => self.set_state(SongState::Paused),
+            SongState::Ended => {
+                log::debug!("toggling ended song");
+            }
+        }
+    }
+
+    fn file_name(&self) -> String {
+        self.inner.read().unwrap().file_name.clone()
+    }
+
+    fn set_device(&mut self, device: Option<&str>) {
+        self.record(Call::SetDevice(device.map(String::from)));
+    }
+
+    fn set_speed(&mut self, speed: f32) {
+        self.record(Call::SetSpeed(speed));
+        self.sync();
+        self.inner.write().unwrap().speed = speed;
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.record(Call::SetVolume(volume));
+    }
+
+    fn position(&self) -> Duration {
+        self.sync();
+        self.inner.read().unwrap().position
+    }
+
+    fn seek(&mut self, position: Duration) {
+        self.record(Call::Seek(position));
+        let duration = self.duration(&self.file_name());
+        self.sync();
+        self.inner.write().unwrap().position = position.min(duration);
+    }
+
+    fn set_loop(&mut self, ab_loop: Option<(Duration, Duration)>) {
+        self.record(Call::SetLoop(ab_loop));
+        self.sync();
+        self.inner.write().unwrap().ab_loop = ab_loop;
+    }
+}
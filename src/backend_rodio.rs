@@ -1,12 +1,25 @@
 use crate::backend_trait::AudioBackend;
 use crate::constants::SongState;
-use crossbeam_channel::unbounded;
+use crate::time_stretch::{Speed, TimeStretch};
+use crate::utils::archive::{self, ReadSeek};
+use anyhow::Result;
+use crossbeam_channel::{unbounded, RecvTimeoutError};
 use log::{debug, error};
-use rodio::{Decoder, OutputStream};
+use rodio::cpal::traits::HostTrait;
+use rodio::{Decoder, DeviceTrait, OutputStream, OutputStreamHandle, Sample, Sink, Source};
 use std::fs::File;
 use std::io::BufReader;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 use std::thread;
+use std::time::Duration;
+
+/// How often the thread checks if the song reached the end of its A-B loop.
+const LOOP_CHECK: Duration = Duration::from_millis(20);
+/// Longer loops are started again on every repeat instead of being kept decoded,
+/// about 50 MB for 44.1 kHz stereo.
+const MAX_SEGMENT: Duration = Duration::from_secs(5 * 60);
 
 enum EventType {
     Start,
@@ -15,6 +28,11 @@ enum EventType {
     SilentPause,
     Stop,
     Tick,
+    Device(Option<String>),
+    Speed(f32),
+    Volume(f32),
+    Seek(Duration),
+    Loop(Option<(Duration, Duration)>),
 }
 
 struct Event {
@@ -22,14 +40,258 @@ struct Event {
     file_name: String,
 }
 
+/// Decoder for an audio file or archive member, also used to render files offline.
+pub fn decode(file_name: &str) -> Result<Decoder<Box<dyn ReadSeek>>> {
+    let source: Box<dyn ReadSeek> = match archive::split_member(file_name) {
+        Some((archive, member)) => archive::open_member(Path::new(archive), member)?,
+        None => Box::new(BufReader::new(File::open(file_name)?)),
+    };
+    debug! {"source: {}", file_name}
+    Ok(Decoder::new(source)?)
+}
+
+/// Names of the available output devices.
+pub fn output_devices() -> Vec<String> {
+    match rodio::cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+        Err(e) => {
+            error! {"Could not list output devices: {e}"};
+            vec![]
+        }
+    }
+}
+
+/// Stream on the named device, or on the default one if not found.
+fn open_stream(device: &Option<String>) -> Result<(OutputStream, OutputStreamHandle)> {
+    if let Some(name) = device {
+        let found = rodio::cpal::default_host()
+            .output_devices()
+            .ok()
+            .and_then(|mut devices| devices.find(|d| d.name().ok().as_ref() == Some(name)));
+        match found.map(|d| OutputStream::try_from_device(&d)) {
+            Some(Ok(stream)) => return Ok(stream),
+            Some(Err(e)) => error! {"Could not open output device {name}: {e}"},
+            None => error! {"Output device {name} not found"},
+        }
+    }
+    Ok(OutputStream::try_default()?)
+}
+
+/// Where we are in the current song, shared with the playing thread.
+#[derive(Clone, Default)]
+struct Progress {
+    samples: Arc<AtomicU64>,
+    samples_per_second: Arc<AtomicU64>,
+}
+
+impl Progress {
+    fn position(&self) -> Duration {
+        match self.samples_per_second.load(Ordering::Relaxed) {
+            0 => Duration::ZERO,
+            rate => {
+                Duration::from_secs_f64(self.samples.load(Ordering::Relaxed) as f64 / rate as f64)
+            }
+        }
+    }
+
+    fn set(&self, position: Duration, samples_per_second: u64) {
+        self.samples_per_second
+            .store(samples_per_second, Ordering::Relaxed);
+        self.samples.store(
+            (position.as_secs_f64() * samples_per_second as f64) as u64,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// Counts the samples played, to know where we are in the song.
+struct Tracked<S> {
+    source: S,
+    samples: Arc<AtomicU64>,
+}
+
+impl<S> Iterator for Tracked<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        let sample = self.source.next();
+        if sample.is_some() {
+            self.samples.fetch_add(1, Ordering::Relaxed);
+        }
+        sample
+    }
+}
+
+impl<S> Source for Tracked<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+/// Append `file_name` to `sink`, starting at `position`.
+fn append(sink: &Sink, file_name: &str, position: Duration, progress: &Progress, speed: &Speed) {
+    match decode(file_name) {
+        Ok(decoder) => {
+            let rate = decoder.channels() as u64 * decoder.sample_rate() as u64;
+            progress.set(position, rate);
+            let tracked = Tracked {
+                source: decoder.skip_duration(position),
+                samples: progress.samples.clone(),
+            };
+            sink.append(TimeStretch::new(tracked.convert_samples(), speed.clone()));
+        }
+        Err(e) => {
+            error! {"{e}"};
+            progress.set(Duration::ZERO, 0);
+        }
+    }
+}
+
+/// The A-B part of a song, decoded once to be repeated without decoding again.
+struct Segment {
+    samples: Arc<[i16]>,
+    channels: u16,
+    sample_rate: u32,
+    start: Duration,
+}
+
+fn decode_segment(file_name: &str, (a, b): (Duration, Duration)) -> Result<Segment> {
+    let decoder = decode(file_name)?;
+    let (channels, sample_rate) = (decoder.channels(), decoder.sample_rate());
+    let samples = decoder
+        .skip_duration(a)
+        .take_duration(b.saturating_sub(a))
+        .collect::<Vec<i16>>();
+    Ok(Segment {
+        samples: samples.into(),
+        channels,
+        sample_rate,
+        start: a,
+    })
+}
+
+/// Plays a segment over and over, moving the progress back to its start on each repeat.
+struct Repeat {
+    samples: Arc<[i16]>,
+    channels: u16,
+    sample_rate: u32,
+    index: usize,
+    start: u64,
+    progress: Arc<AtomicU64>,
+}
+
+impl Repeat {
+    fn new(segment: &Segment, progress: &Progress) -> Repeat {
+        let rate = segment.channels as u64 * segment.sample_rate as u64;
+        progress.set(segment.start, rate);
+        Repeat {
+            samples: segment.samples.clone(),
+            channels: segment.channels,
+            sample_rate: segment.sample_rate,
+            index: 0,
+            start: progress.samples.load(Ordering::Relaxed),
+            progress: progress.samples.clone(),
+        }
+    }
+}
+
+impl Iterator for Repeat {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let sample = *self.samples.get(self.index)?;
+        self.index = (self.index + 1) % self.samples.len();
+        self.progress
+            .store(self.start + self.index as u64, Ordering::Relaxed);
+        Some(sample)
+    }
+}
+
+impl Source for Repeat {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Replace `sink` by a sink on `stream_handle`, filled by `fill` if something
+/// was playing, and paused if it was. `sink` is left as it was when no sink
+/// can be made.
+fn replace_sink(
+    sink: &mut Sink,
+    stream_handle: &OutputStreamHandle,
+    fill: impl FnOnce(&Sink),
+) -> Result<()> {
+    let new_sink = Sink::try_new(stream_handle)?;
+    let paused = sink.is_paused();
+    let playing = !sink.empty();
+    let volume = sink.volume();
+    sink.stop();
+    *sink = new_sink;
+    sink.set_volume(volume);
+    if playing {
+        fill(sink);
+    }
+    if paused {
+        sink.pause();
+    }
+    Ok(())
+}
+
+/// Start the current song again at `position`, paused if it was.
+fn restart(
+    sink: &mut Sink,
+    stream_handle: &OutputStreamHandle,
+    file_name: &str,
+    position: Duration,
+    progress: &Progress,
+    speed: &Speed,
+) -> Result<()> {
+    replace_sink(sink, stream_handle, |sink| {
+        append(sink, file_name, position, progress, speed)
+    })
+}
+
 pub struct Rodio {
     sender: crossbeam_channel::Sender<Event>,
     state: Arc<RwLock<SongState>>,
     file_name: Arc<RwLock<String>>,
+    progress: Progress,
 }
 
 impl Rodio {
-    pub fn new() -> Self {
+    pub fn new(device: Option<String>) -> Self {
         let (sender, receiver) = unbounded();
         let state = Arc::new(RwLock::new(SongState::Ended));
         let state_new = state.clone();
@@ -37,12 +299,61 @@ impl Rodio {
         let file_name = Arc::new(RwLock::new(String::default()));
         let file_name_new = file_name.clone();
 
+        let progress = Progress::default();
+        let progress_new = progress.clone();
+
         thread::spawn(move || -> ! {
-            let (_stream, stream_handle) = OutputStream::try_default().unwrap();
+            let (mut _stream, mut stream_handle) = open_stream(&device).expect("No output device");
             let mut sink = rodio::Sink::try_new(&stream_handle).unwrap();
+            let speed = Speed::new(1.0);
+            let mut ab_loop: Option<(Duration, Duration)> = None;
+            // Decoded in a thread when the loop is set, played from B on
+            let mut segment: Option<Segment> = None;
+            let (segment_sender, segment_receiver) = unbounded();
+            let mut looping = false;
             loop {
-                let event: Event = match receiver.recv() {
+                // Unless the loop or the song changed meanwhile
+                for (decoded_file, decoded_loop, decoded) in segment_receiver.try_iter() {
+                    if ab_loop == Some(decoded_loop) && *file_name.read().unwrap() == decoded_file {
+                        segment = Some(decoded);
+                    }
+                }
+                let event: Event = match receiver.recv_timeout(LOOP_CHECK) {
                     Ok(e) => e,
+                    Err(RecvTimeoutError::Timeout) => {
+                        if let Some((a, b)) = ab_loop {
+                            if !looping && !sink.empty() && progress.position() >= b {
+                                let looped = match &segment {
+                                    Some(segment) => {
+                                        replace_sink(&mut sink, &stream_handle, |sink| {
+                                            let repeat = Repeat::new(segment, &progress);
+                                            sink.append(TimeStretch::new(
+                                                repeat.convert_samples(),
+                                                speed.clone(),
+                                            ));
+                                        })
+                                    }
+                                    // Not decoded, started again on every repeat
+                                    None => {
+                                        let current_file = file_name.read().unwrap().clone();
+                                        restart(
+                                            &mut sink,
+                                            &stream_handle,
+                                            &current_file,
+                                            a,
+                                            &progress,
+                                            &speed,
+                                        )
+                                    }
+                                };
+                                match looped {
+                                    Ok(()) => looping = segment.is_some(),
+                                    Err(e) => error! {"Could not loop: {e}"},
+                                }
+                            }
+                        }
+                        continue;
+                    }
                     Err(err) => {
                         debug!("Error receiving {}", err);
                         panic!("Error event: {err:?}");
@@ -52,22 +363,25 @@ impl Rodio {
                     EventType::Start => {
                         debug! {"Start : switched to {} {}", SongState::Playing, event.file_name};
                         *state.write().unwrap() = SongState::Playing;
+                        looping = false;
+                        segment = None;
                         if !sink.empty() {
                             debug! {"cleaning sink"}
-                            sink.stop();
-                            sink = rodio::Sink::try_new(&stream_handle).unwrap();
-                        }
-                        let source = BufReader::new(File::open(event.file_name.clone()).unwrap());
-                        debug! {"source: {:?}", source}
-                        match Decoder::new(source) {
-                            Ok(decoder) => {
-                                sink.append(decoder);
-                                sink.play();
-                            }
-                            Err(e) => {
-                                error! {"{e}"};
+                            match rodio::Sink::try_new(&stream_handle) {
+                                Ok(new_sink) => {
+                                    let volume = sink.volume();
+                                    sink.stop();
+                                    sink = new_sink;
+                                    sink.set_volume(volume);
+                                }
+                                Err(e) => {
+                                    error! {"Could not clean sink: {e}"};
+                                    sink.clear();
+                                }
                             }
                         }
+                        append(&sink, &event.file_name, Duration::ZERO, &progress, &speed);
+                        sink.play();
 
                         debug! {"Decoder done"};
 
@@ -117,6 +431,93 @@ impl Rodio {
                         debug!("switched to {} {}", SongState::Ended, event.file_name);
                         *state.write().unwrap() = SongState::Ended;
                     }
+                    EventType::Device(device) => {
+                        // Resume the song where it was on the new device, or
+                        // keep playing on the current one
+                        let position = progress.position();
+                        let current_file = file_name.read().unwrap().clone();
+                        let switched = open_stream(&device).and_then(|(stream, handle)| {
+                            restart(
+                                &mut sink,
+                                &handle,
+                                &current_file,
+                                position,
+                                &progress,
+                                &speed,
+                            )?;
+                            Ok((stream, handle))
+                        });
+                        match switched {
+                            Ok(stream) => {
+                                (_stream, stream_handle) = stream;
+                                looping = false;
+                                debug!("switched to device {:?} at {:?}", device, position);
+                            }
+                            Err(e) => error! {"Could not switch to device {:?}: {e}", device},
+                        }
+                    }
+                    EventType::Seek(position) => {
+                        let current_file = file_name.read().unwrap().clone();
+                        match restart(
+                            &mut sink,
+                            &stream_handle,
+                            &current_file,
+                            position,
+                            &progress,
+                            &speed,
+                        ) {
+                            Ok(()) => {
+                                looping = false;
+                                debug!("seeked to {:?}", position);
+                            }
+                            Err(e) => error! {"Could not seek: {e}"},
+                        }
+                    }
+                    EventType::Loop(new_loop) => {
+                        ab_loop = new_loop;
+                        let current_file = file_name.read().unwrap().clone();
+                        segment = None;
+                        match ab_loop {
+                            Some((a, b)) if b.saturating_sub(a) > MAX_SEGMENT => {
+                                debug!("loop {:?} too long to be kept decoded", (a, b));
+                            }
+                            Some(ab) if !current_file.is_empty() => {
+                                let sender = segment_sender.clone();
+                                let file = current_file.clone();
+                                thread::spawn(move || match decode_segment(&file, ab) {
+                                    Ok(decoded) => {
+                                        let _ = sender.send((file, ab, decoded));
+                                    }
+                                    Err(e) => error! {"Could not decode loop {:?}: {e}", ab},
+                                });
+                            }
+                            _ => {}
+                        }
+                        // Go on with the song from where the repeat is
+                        if looping {
+                            looping = false;
+                            let position = progress.position();
+                            if let Err(e) = restart(
+                                &mut sink,
+                                &stream_handle,
+                                &current_file,
+                                position,
+                                &progress,
+                                &speed,
+                            ) {
+                                error! {"Could not leave loop: {e}"};
+                            }
+                        }
+                        debug!("loop {:?}", ab_loop);
+                    }
+                    EventType::Speed(new_speed) => {
+                        speed.set(new_speed);
+                        debug!("speed {}", new_speed);
+                    }
+                    EventType::Volume(volume) => {
+                        sink.set_volume(volume);
+                        debug!("volume {}", volume);
+                    }
                     EventType::Tick => {
                         // Housekeeping
                         if sink.empty() {
@@ -137,6 +538,7 @@ impl Rodio {
             sender,
             state: state_new,
             file_name: file_name_new,
+            progress: progress_new,
         }
     }
 }
@@ -219,6 +621,55 @@ impl AudioBackend for Rodio {
         let test = self.file_name.read().unwrap();
         (*test).to_string()
     }
+
+    fn set_device(&mut self, device: Option<&str>) {
+        self.sender
+            .send(Event {
+                event_type: EventType::Device(device.map(String::from)),
+                file_name: String::default(),
+            })
+            .unwrap();
+    }
+
+    fn set_speed(&mut self, speed: f32) {
+        self.sender
+            .send(Event {
+                event_type: EventType::Speed(speed),
+                file_name: String::default(),
+            })
+            .unwrap();
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.sender
+            .send(Event {
+                event_type: EventType::Volume(volume),
+                file_name: String::default(),
+            })
+            .unwrap();
+    }
+
+    fn position(&self) -> Duration {
+        self.progress.position()
+    }
+
+    fn seek(&mut self, position: Duration) {
+        self.sender
+            .send(Event {
+                event_type: EventType::Seek(position),
+                file_name: String::default(),
+            })
+            .unwrap();
+    }
+
+    fn set_loop(&mut self, ab_loop: Option<(Duration, Duration)>) {
+        self.sender
+            .send(Event {
+                event_type: EventType::Loop(ab_loop),
+                file_name: String::default(),
+            })
+            .unwrap();
+    }
 }
 
 // impl AudioBackend for Rodio {
@@ -286,3 +737,26 @@ impl AudioBackend for Rodio {
 //     Ok((percent_pos, time_pos, duration))
 // }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_repeat_and_move_the_progress_back() {
+        let segment = Segment {
+            samples: Arc::from([1, 2, 3]),
+            channels: 1,
+            sample_rate: 1,
+            start: Duration::from_secs(10),
+        };
+        let progress = Progress::default();
+        let mut repeat = Repeat::new(&segment, &progress);
+        assert_eq!(progress.position(), Duration::from_secs(10));
+
+        assert_eq!(repeat.by_ref().take(2).collect::<Vec<i16>>(), [1, 2]);
+        assert_eq!(progress.position(), Duration::from_secs(12));
+        assert_eq!(repeat.by_ref().take(2).collect::<Vec<i16>>(), [3, 1]);
+        assert_eq!(progress.position(), Duration::from_secs(11));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::constants::SongState;
+use std::time::Duration;
 
 pub trait AudioBackend {
     fn stop(&mut self);
@@ -10,4 +11,15 @@ pub trait AudioBackend {
     fn state(&self) -> SongState;
     fn toggle(&mut self);
     fn file_name(&self) -> String;
+    /// Output device by name, None for the default one.
+    fn set_device(&mut self, device: Option<&str>);
+    /// Playback speed, keeping the pitch.
+    fn set_speed(&mut self, speed: f32);
+    /// Volume from 0 to 1.
+    fn set_volume(&mut self, volume: f32);
+    /// Position in the current song.
+    fn position(&self) -> Duration;
+    fn seek(&mut self, position: Duration);
+    /// Play the current song from A to B again and again.
+    fn set_loop(&mut self, ab_loop: Option<(Duration, Duration)>);
 }
@@ -0,0 +1,176 @@
+use crate::backend_rodio::decode;
+use crate::backend_trait::AudioBackend;
+use crate::constants::SongState;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use log::{debug, error};
+use rodio::source::UniformSourceIterator;
+use rodio::Source;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+// Decodes songs with the same decoder as the Rodio backend, but appends the
+// samples to a wav file instead of playing them. A song is rendered as soon as
+// it is started, so it is already ended on the next autoplay.
+
+struct Inner {
+    path: PathBuf,
+    // Created with the format of the first rendered song
+    writer: Option<WavWriter<BufWriter<File>>>,
+    file_name: String,
+    started: usize,
+}
+
+/// Clones share the same output file.
+#[derive(Clone)]
+pub struct Wav {
+    inner: Arc<RwLock<Inner>>,
+}
+
+impl Wav {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Inner {
+                path: path.to_path_buf(),
+                writer: None,
+                file_name: String::default(),
+                started: 0,
+            })),
+        }
+    }
+
+    /// Number of songs started so far, rendered or not.
+    pub fn started(&self) -> usize {
+        self.inner.read().unwrap().started
+    }
+
+    /// Write the wav header. Nothing is written if no song could be decoded.
+    pub fn finalize(&self) -> Result<(), hound::Error> {
+        match self.inner.write().unwrap().writer.take() {
+            Some(writer) => writer.finalize(),
+            None => Ok(()),
+        }
+    }
+
+    fn render(inner: &mut Inner, file_name: &str) -> anyhow::Result<()> {
+        let source = decode(file_name)?;
+
+        if inner.writer.is_none() {
+            let spec = WavSpec {
+                channels: source.channels(),
+                sample_rate: source.sample_rate(),
+                bits_per_sample: 16,
+                sample_format: SampleFormat::Int,
+            };
+            inner.writer = Some(WavWriter::create(&inner.path, spec)?);
+        }
+        let writer = inner.writer.as_mut().unwrap();
+        let spec = writer.spec();
+
+        let samples: UniformSourceIterator<_, i16> =
+            UniformSourceIterator::new(source, spec.channels, spec.sample_rate);
+        for sample in samples {
+            writer.write_sample(sample)?;
+        }
+        Ok(())
+    }
+}
+
+// Songs are rendered at once, there is nothing to pause or resume.
+impl AudioBackend for Wav {
+    fn stop(&mut self) {}
+
+    fn start(&mut self, file_name: &str) {
+        debug!("rendering {}", file_name);
+        let mut inner = self.inner.write().unwrap();
+        inner.started += 1;
+        inner.file_name = file_name.to_string();
+        if let Err(e) = Self::render(&mut inner, file_name) {
+            error!("Could not render {}: {}", file_name, e);
+        }
+    }
+
+    fn pause(&mut self) {}
+
+    fn silent_pause(&mut self) {}
+
+    fn resume(&mut self) {}
+
+    fn busy(&self) -> bool {
+        false
+    }
+
+    fn state(&self) -> SongState {
+        SongState::Ended
+    }
+
+    fn toggle(&mut self) {}
+
+    fn file_name(&self) -> String {
+        self.inner.read().unwrap().file_name.clone()
+    }
+
+    fn set_device(&mut self, _device: Option<&str>) {}
+
+    fn set_speed(&mut self, _speed: f32) {}
+
+    fn set_volume(&mut self, _volume: f32) {}
+
+    fn position(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn seek(&mut self, _position: Duration) {}
+
+    fn set_loop(&mut self, _ab_loop: Option<(Duration, Duration)>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hound::WavReader;
+    use tempfile::TempDir;
+
+    fn write_song(path: &Path, channels: u16, sample_rate: u32, frames: usize) {
+        let spec = WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(path, spec).unwrap();
+        for i in 0..frames * channels as usize {
+            writer.write_sample((i % 100) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn songs_are_appended_in_first_song_format() {
+        let dir = TempDir::new().unwrap();
+        let first = dir.path().join("first.wav");
+        let second = dir.path().join("second.wav");
+        let broken = dir.path().join("broken.wav");
+        let output = dir.path().join("output.wav");
+        write_song(&first, 2, 44100, 4410);
+        write_song(&second, 1, 22050, 2205);
+        std::fs::write(&broken, b"not a song").unwrap();
+
+        let mut wav = Wav::new(&output);
+        for song in [&first, &broken, &second] {
+            wav.start(song.to_str().unwrap());
+            assert_eq!(wav.state(), SongState::Ended);
+        }
+        wav.finalize().unwrap();
+
+        assert_eq!(wav.started(), 3);
+        let reader = WavReader::open(&output).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        assert_eq!(reader.spec().sample_rate, 44100);
+        // Resampling may be off by a frame
+        let frames = reader.duration() as i64;
+        assert!((frames - 2 * 4410).abs() <= 2, "{frames} frames");
+    }
+}
@@ -0,0 +1,182 @@
+use crate::app::Chord;
+use crate::files::Files;
+use crate::utils;
+use crossterm::event::KeyCode;
+use serde_derive::{Deserialize, Serialize};
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Bookmark {
+    pub position: Duration,
+    pub label: String,
+}
+
+/// Named positions in files, kept in the cache directory.
+pub struct Bookmarks {
+    pub bookmarks: HashMap<String, Vec<Bookmark>>,
+    pub line_index: i32,
+    // Label of the next bookmark, typed in the Bookmarks tab
+    pub label: String,
+    path: PathBuf,
+}
+
+impl Bookmarks {
+    pub fn new() -> Bookmarks {
+        let path = utils::config::get_bookmarks_file().unwrap_or_else(|e| {
+            log::error!("{e}");
+            // Nothing is loaded, saving fails
+            PathBuf::default()
+        });
+        Self::load(path)
+    }
+
+    pub fn load(path: PathBuf) -> Bookmarks {
+        let bookmarks = File::open(&path)
+            .ok()
+            .and_then(|file| serde_json::from_reader(file).ok())
+            .unwrap_or_default();
+
+        Bookmarks {
+            bookmarks,
+            line_index: 0,
+            label: String::default(),
+            path,
+        }
+    }
+
+    fn save(&self) {
+        if let Some(parent) = self.path.parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                log::error!("Could not create {:?}: {e}", parent);
+                return;
+            }
+        }
+        match File::create(&self.path) {
+            Ok(file) => {
+                if let Err(e) = serde_json::to_writer_pretty(file, &self.bookmarks) {
+                    log::error!("Could not save bookmarks: {e}");
+                }
+            }
+            Err(e) => log::error!("Could not create {:?}: {e}", self.path),
+        }
+    }
+
+    /// Bookmarks of `file_name`, by position.
+    pub fn of(&self, file_name: &str) -> &[Bookmark] {
+        self.bookmarks
+            .get(file_name)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn add(&mut self, file_name: &str, position: Duration, label: &str) {
+        let bookmarks = self.bookmarks.entry(file_name.to_string()).or_default();
+        bookmarks.push(Bookmark {
+            position,
+            label: label.to_string(),
+        });
+        bookmarks.sort_by_key(|bookmark| bookmark.position);
+        self.save();
+    }
+
+    pub fn remove(&mut self, file_name: &str, index: usize) {
+        let Some(bookmarks) = self.bookmarks.get_mut(file_name) else {
+            return;
+        };
+        if index < bookmarks.len() {
+            bookmarks.remove(index);
+        }
+        if bookmarks.is_empty() {
+            self.bookmarks.remove(file_name);
+        }
+        self.save();
+    }
+
+    /// Keys typed in the label.
+    pub fn handle_event(&mut self, chord: Chord) {
+        match chord {
+            (_, KeyCode::Backspace) => {
+                self.label.pop();
+            }
+            (_, KeyCode::Char(c)) => self.label.push(c),
+            _ => {}
+        }
+    }
+
+    pub fn line_down(&mut self, files: &Files) {
+        let length = self.of(&files.current_file).len() as i32;
+        self.line_index = cmp::max(cmp::min(self.line_index + 1, length - 1), 0);
+    }
+
+    pub fn line_up(&mut self) {
+        self.line_index = cmp::max(self.line_index - 1, 0);
+    }
+
+    /// Add a bookmark when a label is typed, jump to the selected one otherwise.
+    pub fn select(&mut self, files: &mut Files) {
+        let file_name = files.current_file.clone();
+        if file_name.is_empty() {
+            return;
+        }
+        if self.label.is_empty() {
+            // Jump to the selected bookmark
+            if let Some(bookmark) = self.of(&file_name).get(self.line_index as usize) {
+                files.seek(bookmark.position);
+            }
+        } else {
+            let label = std::mem::take(&mut self.label);
+            self.add(&file_name, files.position(), &label);
+        }
+    }
+
+    pub fn remove_line(&mut self, files: &Files) {
+        let file_name = files.current_file.clone();
+        let length = self.of(&file_name).len() as i32;
+        self.remove(&file_name, self.line_index as usize);
+        self.line_index = cmp::max(cmp::min(self.line_index, length - 2), 0);
+    }
+}
+
+impl Default for Bookmarks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn bookmarks_are_sorted_and_saved() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("bookmarks.json");
+        let mut bookmarks = Bookmarks::load(path.clone());
+        bookmarks.add("song.mp3", Duration::from_secs(90), "solo");
+        bookmarks.add("song.mp3", Duration::from_secs(30), "verse");
+        bookmarks.add("other.mp3", Duration::from_secs(10), "intro");
+        bookmarks.remove("other.mp3", 0);
+
+        let bookmarks = Bookmarks::load(path);
+        assert_eq!(
+            bookmarks.of("song.mp3"),
+            [
+                Bookmark {
+                    position: Duration::from_secs(30),
+                    label: String::from("verse"),
+                },
+                Bookmark {
+                    position: Duration::from_secs(90),
+                    label: String::from("solo"),
+                },
+            ]
+        );
+        assert!(bookmarks.of("other.mp3").is_empty());
+        assert!(!bookmarks.bookmarks.contains_key("other.mp3"));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::utils::config::{ConfigFormat, PlayMode};
+use argh::FromArgs;
+use std::path::PathBuf;
+
+#[derive(FromArgs)]
+/// Play the music files in a directory, one after the other.
+pub struct Args {
+    /// output device, saved in the config, see `dirplayer devices`
+    #[argh(option, short = 'd')]
+    pub device: Option<String>,
+
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum Command {
+    Render(RenderArgs),
+    Devices(DevicesArgs),
+    Config(ConfigArgs),
+}
+
+#[derive(FromArgs)]
+/// Render files into a single wav file instead of playing them.
+#[argh(subcommand, name = "render")]
+pub struct RenderArgs {
+    /// fuzzy filter, as typed in the Files tab
+    #[argh(option, short = 'f', default = "String::default()")]
+    pub filter: String,
+
+    /// queue or random, defaults to the configured play mode
+    #[argh(option, short = 'm')]
+    pub mode: Option<PlayMode>,
+
+    /// number of files to render, defaults to all filtered files
+    #[argh(option, short = 'n')]
+    pub count: Option<usize>,
+
+    /// wav file to write
+    #[argh(positional)]
+    pub output: PathBuf,
+}
+
+#[derive(FromArgs)]
+/// List the output devices, the configured one is starred.
+#[argh(subcommand, name = "devices")]
+pub struct DevicesArgs {}
+
+#[derive(FromArgs)]
+/// Manage the config file.
+#[argh(subcommand, name = "config")]
+pub struct ConfigArgs {
+    #[argh(subcommand)]
+    pub command: ConfigCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum ConfigCommand {
+    Migrate(MigrateArgs),
+}
+
+#[derive(FromArgs)]
+/// Convert the config file between json and toml, toml being read first.
+#[argh(subcommand, name = "migrate")]
+pub struct MigrateArgs {
+    /// json or toml, defaults to the format not in use
+    #[argh(option)]
+    pub to: Option<ConfigFormat>,
+}
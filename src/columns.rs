@@ -0,0 +1,296 @@
+use crate::files::FileEntry;
+use crate::utils;
+use crate::utils::archive;
+use crate::utils::tags::Tags;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Utc};
+use ratatui::layout::Constraint;
+use serde_derive::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+use strum::{Display, EnumString};
+
+// Columns of the Files table, as chosen in the config. Only the file name shows
+// the characters matching the filter, and only the dates are colored.
+
+pub const DATE_FORMAT: &str = "%Y-%m-%d %H-%M-%S";
+
+#[derive(Serialize, Deserialize, Display, EnumString, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ColumnKind {
+    /// With the archive for archive members
+    Filename,
+    /// From the working directory
+    RelativePath,
+    ParentDir,
+    Title,
+    Artist,
+    Album,
+    Duration,
+    Size,
+    Created,
+    Modified,
+    PlayCount,
+    Rating,
+    Extension,
+    /// Working directory the file was found in
+    Source,
+}
+
+impl ColumnKind {
+    /// Read from the tags of the file.
+    pub fn is_tag(self) -> bool {
+        matches!(
+            self,
+            ColumnKind::Title
+                | ColumnKind::Artist
+                | ColumnKind::Album
+                | ColumnKind::Duration
+                | ColumnKind::Rating
+        )
+    }
+}
+
+/// A column, as written in the config. Unknown kinds are reported by the columns.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ColumnConfig {
+    pub kind: String,
+    /// In cells, columns without one share the rest of the line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u16>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Column {
+    pub kind: ColumnKind,
+    pub width: Option<u16>,
+}
+
+pub fn default_columns() -> Vec<ColumnConfig> {
+    [
+        (ColumnKind::Filename, None),
+        (ColumnKind::Created, Some(20)),
+    ]
+    .into_iter()
+    .map(|(kind, width)| ColumnConfig {
+        kind: kind.to_string(),
+        width,
+    })
+    .collect()
+}
+
+pub fn default_date_format() -> String {
+    String::from(DATE_FORMAT)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Columns {
+    pub columns: Vec<Column>,
+    pub date_format: String,
+    /// Unknown kinds, no columns or an invalid date format
+    pub errors: Vec<String>,
+}
+
+impl Columns {
+    pub fn new(columns: &[ColumnConfig], date_format: &str) -> Columns {
+        let mut errors = vec![];
+        let mut parse = |column: &ColumnConfig| match ColumnKind::from_str(&column.kind) {
+            Ok(kind) => Some(Column {
+                kind,
+                width: column.width,
+            }),
+            Err(_) => {
+                errors.push(format!("Unknown column {}", column.kind));
+                None
+            }
+        };
+        let mut parsed = columns.iter().filter_map(&mut parse).collect::<Vec<_>>();
+        if parsed.is_empty() {
+            parsed = default_columns().iter().filter_map(parse).collect();
+            errors.push(String::from("No columns"));
+        }
+        let columns = parsed;
+        // Formatting with an invalid format panics
+        let date_format = match StrftimeItems::new(date_format).any(|item| item == Item::Error) {
+            true => {
+                errors.push(format!("Invalid date format {date_format}"));
+                default_date_format()
+            }
+            false => date_format.to_string(),
+        };
+        Columns {
+            columns,
+            date_format,
+            errors,
+        }
+    }
+
+    pub fn constraints(columns: &[Column]) -> Vec<Constraint> {
+        columns
+            .iter()
+            .map(|column| match column.width {
+                Some(width) => Constraint::Length(width),
+                None => Constraint::Fill(1),
+            })
+            .collect()
+    }
+
+    pub fn format_date(&self, date: SystemTime) -> String {
+        DateTime::<Utc>::from(date)
+            .format(&self.date_format)
+            .to_string()
+    }
+
+    /// Text of the `kind` column for `entry`, empty when the tags are not read.
+    pub fn text(
+        &self,
+        kind: ColumnKind,
+        entry: &FileEntry,
+        tags: Option<&Tags>,
+        play_count: u32,
+    ) -> String {
+        let tags = tags.cloned().unwrap_or_default();
+        match kind {
+            ColumnKind::Filename => entry.file_name().to_string_lossy().to_string(),
+            ColumnKind::RelativePath => entry
+                .path()
+                .strip_prefix(entry.source())
+                .unwrap_or(entry.path())
+                .to_string_lossy()
+                .to_string(),
+            ColumnKind::ParentDir => parent_dir(&entry.path().to_string_lossy()),
+            ColumnKind::Title => tags.title.unwrap_or_default(),
+            ColumnKind::Artist => tags.artist.unwrap_or_default(),
+            ColumnKind::Album => tags.album.unwrap_or_default(),
+            ColumnKind::Duration => tags
+                .duration
+                .map(utils::format_duration)
+                .unwrap_or_default(),
+            ColumnKind::Size => entry.size.map(format_size).unwrap_or_default(),
+            ColumnKind::Created => self.format_date(entry.created),
+            ColumnKind::Modified => self.format_date(entry.modified),
+            ColumnKind::PlayCount => match play_count {
+                0 => String::default(),
+                count => count.to_string(),
+            },
+            ColumnKind::Rating => tags.rating.unwrap_or_default(),
+            ColumnKind::Extension => Path::new(entry.file_name())
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            ColumnKind::Source => entry.source().to_string_lossy().to_string(),
+        }
+    }
+}
+
+/// Name of the directory of the file, or of the archive holding it.
+fn parent_dir(path: &str) -> String {
+    let parent = match archive::split_member(path) {
+        Some((archive, member)) => match Path::new(member).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new(archive),
+        },
+        None => Path::new(path).parent().unwrap_or(Path::new("")),
+    };
+    parent
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{size} B");
+    }
+    let mut size = size as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::directory;
+    use std::fs;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn cells_are_taken_from_the_entry_and_its_tags() {
+        let music = TempDir::new().unwrap();
+        fs::create_dir(music.path().join("album")).unwrap();
+        fs::write(music.path().join("album/song.mp3"), [0; 2048]).unwrap();
+        let dir_entry = directory::get_direntries(music.path(), &[String::from("mp3")]).remove(0);
+        let entry = FileEntry::from_dir_entry(&dir_entry, music.path()).unwrap();
+        let tags = Tags {
+            artist: Some(String::from("Artist")),
+            duration: Some(Duration::from_secs(75)),
+            ..Tags::default()
+        };
+
+        let columns = Columns::new(&default_columns(), "%Y");
+        let text = |kind| columns.text(kind, &entry, Some(&tags), 3);
+        assert_eq!(text(ColumnKind::RelativePath), "album/song.mp3");
+        assert_eq!(text(ColumnKind::ParentDir), "album");
+        assert_eq!(text(ColumnKind::Artist), "Artist");
+        assert_eq!(text(ColumnKind::Title), "");
+        assert_eq!(text(ColumnKind::Duration), "01:15");
+        assert_eq!(text(ColumnKind::Size), "2.0 KiB");
+        assert_eq!(text(ColumnKind::PlayCount), "3");
+        assert_eq!(text(ColumnKind::Extension), "mp3");
+        assert_eq!(text(ColumnKind::Created).len(), 4);
+        assert_eq!(
+            columns.text(ColumnKind::Duration, &entry, None, 0),
+            String::default()
+        );
+
+        let archive = music.path().join("album.zip");
+        fs::write(&archive, b"zip").unwrap();
+        let archive = archive.to_string_lossy();
+        assert_eq!(parent_dir(&format!("{archive}!/disc1/01.flac")), "disc1");
+        assert_eq!(parent_dir(&format!("{archive}!/01.flac")), "album.zip");
+    }
+
+    #[test]
+    fn invalid_settings_fall_back_to_the_defaults() {
+        let defaults = Columns::new(&default_columns(), DATE_FORMAT);
+        assert!(defaults.errors.is_empty());
+
+        let columns = Columns::new(&[], "%Y-%Q");
+        assert_eq!(columns.columns, defaults.columns);
+        assert_eq!(columns.date_format, DATE_FORMAT);
+        assert_eq!(
+            columns.errors,
+            vec!["No columns", "Invalid date format %Y-%Q"]
+        );
+
+        let typo = ColumnConfig {
+            kind: String::from("titel"),
+            width: None,
+        };
+        let size = ColumnConfig {
+            kind: String::from("size"),
+            width: Some(10),
+        };
+        let columns = Columns::new(&[typo.clone(), size], DATE_FORMAT);
+        assert_eq!(
+            columns.columns,
+            vec![Column {
+                kind: ColumnKind::Size,
+                width: Some(10),
+            }]
+        );
+        assert_eq!(columns.errors, vec!["Unknown column titel"]);
+
+        let columns = Columns::new(&[typo], DATE_FORMAT);
+        assert_eq!(columns.columns, defaults.columns);
+        assert_eq!(columns.errors, vec!["Unknown column titel", "No columns"]);
+    }
+}
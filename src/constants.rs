@@ -1,4 +1,5 @@
 use core::fmt;
+use std::time::Duration;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum SongState {
@@ -14,3 +15,18 @@ impl fmt::Display for SongState {
 }
 
 pub const ECHO_SIZE: i32 = 5;
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 3.0;
+pub const SPEED_STEP: f32 = 0.1;
+
+pub const VOLUME_STEP: f32 = 0.05;
+
+/// Two clicks on the same line within this time play it.
+pub const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// Threads extracting archives.
+pub const ARCHIVE_WORKERS: usize = 2;
+
+/// Config and state changes are saved once they settle for this long.
+pub const SAVE_DELAY: Duration = Duration::from_millis(500);
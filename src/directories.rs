@@ -2,12 +2,13 @@ use crate::app::get_path_completions;
 use crate::app::Chord;
 use crate::utils;
 use crate::utils::config;
-use crate::utils::config::Config;
 use crate::utils::config::Status;
 use crate::utils::config::WorkingPath;
+use crate::utils::settings::Settings;
 use crossterm::event::KeyCode;
 use crossterm::event::KeyModifiers;
 use itertools::Itertools;
+use ratatui::style::Style;
 use ratatui::text::{Line, Span};
 use std::collections::VecDeque;
 use std::path::Path;
@@ -30,20 +31,24 @@ pub struct Directories<'a> {
     pub working_directories: VecDeque<WorkingPath>,
     pub line_index: i32,
     pub state: State,
+    settings: Settings,
 }
 
 impl<'a> Directories<'a> {
-    pub fn new() -> Directories<'a> {
-        let config = utils::config::get_config();
+    pub fn new(settings: Settings) -> Directories<'a> {
+        let config = settings.config();
         let scoring = Scoring {
             bonus_consecutive: 64,
             bonus_word_start: 1,
             bonus_match_case: 8,
             penalty_distance: 16,
         };
-        let mut working_directory = config.working_directory.clone();
+        let mut working_directory = settings.state().working_directory;
         if working_directory.is_empty() {
-            working_directory = String::from(config::get_audio_dir().to_string_lossy());
+            working_directory = config::get_audio_dir()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
         }
 
         let working_directories = config.working_directories;
@@ -58,10 +63,12 @@ impl<'a> Directories<'a> {
             working_directories,
             line_index: 0,
             state: State::Base,
+            settings,
         }
     }
 
-    pub fn get_displayable_completions(&self) -> Vec<Vec<Span<'a>>> {
+    /// Completions matching the filter, with matched characters in `match_style`.
+    pub fn get_displayable_completions(&self, match_style: Style) -> Vec<Vec<Span<'a>>> {
         let completions = VecDeque::from(self.completions.clone());
 
         let filter = self.filter.clone();
@@ -84,7 +91,8 @@ impl<'a> Directories<'a> {
                     }
 
                     let indices = matched.matched_indices().copied().collect_vec();
-                    let added_spans = utils::style::string_to_styled_spans(completion, indices);
+                    let added_spans =
+                        utils::style::string_to_styled_spans(completion, indices, match_style);
                     Some(added_spans)
                 } else {
                     None
@@ -130,16 +138,13 @@ impl<'a> Directories<'a> {
         });
 
         //
-        let config = utils::config::get_config();
-        utils::config::update_config(&Config {
-            working_directories: self.working_directories.clone(),
-            ..config
-        });
+        self.settings
+            .modify_config(|config| config.working_directories = self.working_directories.clone());
         self.line_index += 1;
     }
 
+    /// Keys typed in the candidate line.
     pub fn handle_event(&mut self, chord: Chord) {
-        let edit_index = self.working_directories.len() - 1;
         match self.state {
             State::Editing => match chord {
                 // (_, KeyCode::Down) => {}
@@ -152,7 +157,8 @@ impl<'a> Directories<'a> {
                 (KeyModifiers::NONE, KeyCode::Right) => self.rotate_idx -= 1,
                 (KeyModifiers::NONE, KeyCode::Left) => {
                     self.rotate_idx += 1;
-                    let displayable_completions = self.get_displayable_completions();
+                    let displayable_completions =
+                        self.get_displayable_completions(Style::default());
                     self.displayable_completions = displayable_completions;
                     // self.displayable_completions = VecDeque::from(vec![displayable_completions]);
                 }
@@ -160,7 +166,8 @@ impl<'a> Directories<'a> {
                     log::debug!("KeyCode::Tab start {:?}", self.completions);
 
                     // Go into directory
-                    let displayable_completions = self.get_displayable_completions();
+                    let displayable_completions =
+                        self.get_displayable_completions(Style::default());
 
                     log::debug!(
                         "KeyCode::Tab displayable_completions {:?}",
@@ -176,7 +183,9 @@ impl<'a> Directories<'a> {
                     let new_directory = Path::new(&self.working_directory)
                         .join(Line::from(displayable_completions[0].clone()).to_string());
                     self.working_directory = String::from(new_directory.to_string_lossy());
-                    utils::config::update_working_directory(&self.working_directory);
+                    self.settings.modify_state(|state| {
+                        state.working_directory = self.working_directory.clone()
+                    });
 
                     // Cancel current filter
                     self.filter = String::from("");
@@ -196,7 +205,9 @@ impl<'a> Directories<'a> {
                         let mut path = PathBuf::from(&self.working_directory);
                         path.pop();
                         self.working_directory = String::from(path.to_string_lossy());
-                        utils::config::update_working_directory(&self.working_directory);
+                        self.settings.modify_state(|state| {
+                            state.working_directory = self.working_directory.clone()
+                        });
                         self.completions = get_path_completions(&self.working_directory);
                     } else {
                         let mut chars = self.filter.chars();
@@ -212,34 +223,50 @@ impl<'a> Directories<'a> {
                 }
                 _ => {}
             },
-            State::Base => match chord {
-                (_, KeyCode::Down) => {
-                    if self.line_index as usize >= edit_index {
-                        self.state = State::Editing;
-                    }
+            // Keys are bound to actions
+            State::Base => {}
+        }
+    }
 
-                    if (self.line_index as usize) < edit_index + 1 {
-                        self.line_index += 1;
-                    }
-                }
-                (_, KeyCode::Up) => {
-                    if self.line_index >= 0 {
-                        self.line_index -= 1;
-                    }
-                }
-                (_, KeyCode::Char(c)) => {
-                    if c == 'k' {
-                        self.working_directories.remove(self.line_index as usize);
-                    }
-                }
-                _ => {}
-            },
+    /// Down to the candidate line after the last directory.
+    pub fn line_down(&mut self) {
+        let edit_index = self.working_directories.len() - 1;
+        if self.line_index as usize >= edit_index {
+            self.state = State::Editing;
+        }
+
+        if (self.line_index as usize) < edit_index + 1 {
+            self.line_index += 1;
         }
     }
-}
 
-impl<'a> Default for Directories<'a> {
-    fn default() -> Self {
-        Self::new()
+    pub fn line_up(&mut self) {
+        if self.line_index >= 0 {
+            self.line_index -= 1;
+        }
+    }
+
+    /// Type a new directory in the candidate line.
+    pub fn start_adding(&mut self) {
+        self.state = State::Editing;
+        self.line_index = self.working_directories.len() as i32;
+    }
+
+    /// Deactivate the directory, or activate it back. The cache keeps its status.
+    pub fn toggle_line(&mut self) {
+        let Some(working_path) = self.working_directories.get_mut(self.line_index as usize) else {
+            return;
+        };
+        working_path.status = match working_path.status {
+            Status::Active => Status::Inactive,
+            Status::Inactive => Status::Active,
+            Status::Cache => return,
+        };
+        self.settings
+            .modify_config(|config| config.working_directories = self.working_directories.clone());
+    }
+
+    pub fn remove_line(&mut self) {
+        self.working_directories.remove(self.line_index as usize);
     }
 }
@@ -1,11 +1,11 @@
 use crate::constants::ECHO_SIZE;
+use crate::theme::Theme;
 use chrono::offset;
 // use crossbeam::channel::{unbounded, Receiver, Sender};
 use crossbeam::channel::unbounded;
 use log::debug;
 use ratatui::{
     layout::Constraint,
-    style::Style,
     widgets::{Cell, Row, Table},
 };
 use std::{
@@ -62,16 +62,12 @@ impl EchoArea {
         }
     }
 
-    pub fn draw(&self, f: &mut ratatui::Frame, chunk: ratatui::layout::Rect) {
+    pub fn draw(&self, f: &mut ratatui::Frame, chunk: ratatui::layout::Rect, theme: &Theme) {
         let messages = self.messages.read().unwrap();
 
         let list_items: Vec<Row> = messages
             .iter()
-            .map(|message| {
-                Row::new(vec![Cell::from((*message).to_string()).style(
-                    Style::default().fg(ratatui::style::Color::Rgb(255, 255, 0)),
-                )])
-            })
+            .map(|message| Row::new(vec![Cell::from((*message).to_string()).style(theme.echo)]))
             .collect();
 
         let constraints = &[Constraint::Percentage(80), Constraint::Length(30)];
@@ -1,24 +1,32 @@
 use crate::app::Chord;
 #[cfg(feature = "mpv")]
 use crate::backend_mpv::Mpv;
+use crate::backend_null::Null;
+use crate::backend_rodio;
 use crate::backend_rodio::Rodio;
 use crate::backend_trait::AudioBackend;
-use crate::constants::SongState;
+use crate::constants::{SongState, ARCHIVE_WORKERS, MAX_SPEED, MIN_SPEED};
 use crate::utils;
-use crate::utils::config::{Config, Status, WorkingPath};
+use crate::utils::archive::{self, ArchiveOptions, ArchiveStatus};
+use crate::utils::archive_cache;
+use crate::utils::archive_workers::ArchiveWorkers;
+use crate::utils::config::{ArchiveMode, Backend, Config, PlayMode, Sort, Status, WorkingPath};
+use crate::utils::settings::Settings;
+use crate::utils::tags::{TagReader, Tags};
 use chrono::{Datelike, NaiveDate};
 use chrono::{NaiveDateTime, Utc};
 use crossbeam_channel::unbounded;
-use crossterm::event::poll;
-use crossterm::event::{KeyCode, KeyModifiers};
+use crossterm::event::KeyCode;
 use itertools::Itertools;
 use log::debug;
 use notify::{watcher, RecursiveMode, Watcher};
 use rand::{thread_rng, Rng};
 use ratatui::Frame;
 use std::cmp;
-use std::collections::VecDeque;
-use std::path::PathBuf;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsStr;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::{Duration, SystemTime};
@@ -26,10 +34,78 @@ use sublime_fuzzy::{FuzzySearch, Scoring};
 use utils::directory;
 use walkdir::DirEntry;
 
+/// A playable file, or a member of an archive, as "album.zip!/disc1/01.flac".
+#[derive(Clone, Debug)]
+pub struct FileEntry {
+    path: String,
+    file_name: String,
+    // Working directory it was found in
+    source: String,
+    pub created: SystemTime,
+    pub modified: SystemTime,
+    // Unknown for archive members
+    pub size: Option<u64>,
+}
+
+impl FileEntry {
+    /// A file found in the working directory `source`.
+    pub fn from_dir_entry(dir_entry: &DirEntry, source: &Path) -> Option<FileEntry> {
+        let metadata = dir_entry.metadata().ok()?;
+        Some(FileEntry {
+            path: dir_entry.path().to_string_lossy().to_string(),
+            file_name: dir_entry.file_name().to_string_lossy().to_string(),
+            source: source.to_string_lossy().to_string(),
+            created: metadata.created().ok()?,
+            modified: metadata.modified().ok()?,
+            size: Some(metadata.len()),
+        })
+    }
+
+    /// Members of the archive, dated as the archive.
+    fn from_archive(dir_entry: &DirEntry, source: &Path, extensions: &[String]) -> Vec<FileEntry> {
+        let Some(archive) = FileEntry::from_dir_entry(dir_entry, source) else {
+            return vec![];
+        };
+        match archive::list_members(dir_entry.path(), extensions) {
+            Ok(members) => members
+                .iter()
+                .map(|member| FileEntry {
+                    path: archive::member_path(dir_entry.path(), member),
+                    file_name: format!(
+                        "{}{}{member}",
+                        archive.file_name,
+                        archive::MEMBER_SEPARATOR
+                    ),
+                    source: archive.source.clone(),
+                    created: archive.created,
+                    modified: archive.modified,
+                    size: None,
+                })
+                .collect_vec(),
+            Err(e) => {
+                log::error!("Could not list {:?}: {e}", dir_entry.path());
+                vec![]
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        Path::new(&self.path)
+    }
+
+    pub fn file_name(&self) -> &OsStr {
+        OsStr::new(&self.file_name)
+    }
+
+    pub fn source(&self) -> &Path {
+        Path::new(&self.source)
+    }
+}
+
 // #[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
 #[derive(std::clone::Clone)]
 pub struct FileLine {
-    pub dir_entry: DirEntry,
+    pub dir_entry: FileEntry,
     pub indices: Vec<usize>,
 }
 
@@ -38,30 +114,59 @@ pub struct Files {
     pub current_file: String,
     pub filter: String,
     pub line_index: i32,
-    pub lines: Arc<RwLock<Vec<DirEntry>>>,
+    pub lines: Arc<RwLock<Vec<FileEntry>>>,
     pub lines_filtered: Vec<FileLine>,
     pub scoring: Scoring,
     #[cfg(feature = "mpv")]
-    pub mpv_client: Mpv,
+    pub mpv_client: Box<dyn AudioBackend>,
 
     pub paths: Arc<RwLock<Vec<String>>>,
     // pub path: Arc<RwLock<PathBuf>>,
     pub receiver: crossbeam_channel::Receiver<std::result::Result<notify::Event, notify::Error>>,
-    pub rodio_client: Rodio,
+    pub rodio_client: Box<dyn AudioBackend>,
     pub dir_changed: Arc<RwLock<bool>>,
+    // The config file was changed
+    config_changed: Arc<RwLock<bool>>,
     pub watcher: notify::INotifyWatcher,
     pub extensions: Vec<String>,
     extensions_archives: Vec<String>,
+    archive_mode: ArchiveMode,
+    archive_cache_max_mb: Option<u64>,
+    archive_passwords: HashMap<String, String>,
+    nested_archive_depth: usize,
+    pub archive_status: Arc<RwLock<ArchiveStatus>>,
+    archive_workers: Arc<RwLock<Option<ArchiveWorkers>>>,
+    pub play_mode: PlayMode,
+    pub sort: Sort,
+    pub output_device: Option<String>,
+    pub speed: f32,
+    pub speeds: HashMap<String, f32>,
+    // From 0 to 1
+    pub volume: f32,
+    // Of the files shown, read when a column needs them, and of the current file
+    tag_reader: TagReader,
+    play_counts: HashMap<String, u32>,
+    // A and B points of the loop in the current file
+    pub ab_loop: (Option<Duration>, Option<Duration>),
+    settings: Settings,
 }
 
 impl Files {
-    pub fn new() -> Files {
+    pub fn new(settings: Settings) -> Files {
+        let config = settings.config();
+        let state = settings.state();
         let (sender, receiver) = unbounded();
         let mut watcher = watcher(sender.clone(), Duration::from_secs(1)).unwrap();
 
-        let config = utils::config::get_config();
         let default_paths = config.working_directories.clone();
 
+        // Editors may replace the file, its directory is watched instead
+        if let Some(config_dir) = settings.config_file().as_deref().and_then(Path::parent) {
+            if let Err(e) = watcher.watch(config_dir, RecursiveMode::NonRecursive) {
+                log::error!("Could not watch {:?}: {e}", config_dir);
+            }
+        }
+
         default_paths.iter().for_each(|working_path| {
             watcher
                 .watch(&working_path.path, RecursiveMode::Recursive)
@@ -81,25 +186,60 @@ impl Files {
             .map(|WorkingPath { path, .. }| (*path).clone())
             .collect_vec();
 
-        let current_file = config.current_file;
-        Files {
+        let null = Null::new();
+        let mut files = Files {
             _sender: sender,
             // MAYBE WRONG
-            current_file,
+            current_file: state.current_file,
             filter: String::default(),
             line_index: 0,
             lines: Arc::new(RwLock::new(Vec::new())),
             lines_filtered: Vec::new(),
             scoring,
-            mpv_client: Mpv::new(),
-            rodio_client: Rodio::new(),
+            #[cfg(feature = "mpv")]
+            mpv_client: match config.backend {
+                Backend::Native => Box::new(Mpv::new(config.output_device.as_deref())),
+                Backend::Null => Box::new(null.clone()),
+            },
+            rodio_client: match config.backend {
+                Backend::Native => Box::new(Rodio::new(config.output_device.clone())),
+                Backend::Null => Box::new(null),
+            },
             paths: Arc::new(RwLock::new(paths)),
             receiver,
             dir_changed: Arc::new(RwLock::new(false)),
+            config_changed: Arc::new(RwLock::new(false)),
             watcher,
             extensions: config.extensions,
             extensions_archives: config.extensions_archives,
+            archive_mode: config.archive_mode,
+            archive_cache_max_mb: config.archive_cache_max_mb,
+            archive_passwords: config.archive_passwords,
+            nested_archive_depth: config.nested_archive_depth,
+            archive_status: Arc::new(RwLock::new(ArchiveStatus::default())),
+            archive_workers: Arc::new(RwLock::new(None)),
+            play_mode: state.play_mode,
+            sort: state.sort,
+            output_device: config.output_device,
+            speed: 1.0,
+            speeds: config.speeds,
+            volume: state.volume,
+            tag_reader: TagReader::start(),
+            play_counts: state.play_counts,
+            ab_loop: (None, None),
+            settings,
+        };
+        files.apply_volume();
+        files
+    }
+
+    /// Play every file with `backend`, whatever the config says.
+    pub fn set_backend<B: AudioBackend + Clone + 'static>(&mut self, backend: B) {
+        #[cfg(feature = "mpv")]
+        {
+            self.mpv_client = Box::new(backend.clone());
         }
+        self.rodio_client = Box::new(backend);
     }
 
     pub fn update_lines(&mut self) {
@@ -112,20 +252,76 @@ impl Files {
             .collect_vec();
 
         let mut new_lines = paths
-            .into_iter()
-            .flat_map(|path| directory::get_direntries(&path, &self.extensions))
+            .iter()
+            .flat_map(|path| {
+                directory::get_direntries(path, &self.extensions)
+                    .into_iter()
+                    .filter_map(|dir_entry| FileEntry::from_dir_entry(&dir_entry, path))
+            })
             .collect_vec();
 
-        new_lines.sort_by(|a, b| {
-            let creation_a = a.metadata().unwrap().created().unwrap();
-            let creation_b = b.metadata().unwrap().created().unwrap();
-            creation_b.partial_cmp(&creation_a).unwrap()
-        });
+        if self.archive_mode == ArchiveMode::Virtual {
+            let zip = self.virtual_extensions_archives();
+            let extensions = &self.extensions;
+            new_lines.extend(paths.iter().flat_map(|path| {
+                directory::get_direntries(path, &zip)
+                    .into_iter()
+                    .flat_map(|dir_entry| FileEntry::from_archive(&dir_entry, path, extensions))
+            }));
+        }
+
+        match self.sort {
+            Sort::Newest => new_lines.sort_by_key(|line| cmp::Reverse(line.created)),
+            Sort::Oldest => new_lines.sort_by_key(|line| line.created),
+            Sort::Name => new_lines.sort_by_key(|line| line.file_name.to_lowercase()),
+        }
+
+        // Files may have been tagged again
+        let modified: HashMap<&str, SystemTime> = new_lines
+            .iter()
+            .map(|line| (line.path.as_str(), line.modified))
+            .collect();
+        self.tag_reader
+            .forget_changed(|path| modified.get(path).copied());
+        self.queue_current_tags();
 
         let mut lines = self.lines.write().unwrap();
         *lines = new_lines;
     }
 
+    /// Queue the tags of the filtered lines in `range` that were not read yet.
+    pub fn read_tags_of(&self, range: Range<usize>) {
+        for line in &self.lines_filtered[range] {
+            self.tag_reader
+                .queue(&line.dir_entry.path, line.dir_entry.modified);
+        }
+    }
+
+    pub fn cached_tags(&self, entry: &FileEntry) -> Option<Tags> {
+        self.tag_reader.get(&entry.path)
+    }
+
+    /// Queue the tags of the current file, dated as its archive for a member.
+    fn queue_current_tags(&self) {
+        let current_file = &self.current_file;
+        let path = archive::split_member(current_file).map_or(current_file.as_str(), |(a, _)| a);
+        if let Ok(modified) = std::fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            self.tag_reader.queue(current_file, modified);
+        }
+    }
+
+    /// Tags of the current file, empty until read.
+    pub fn current_tags(&self) -> Tags {
+        self.tag_reader.get(&self.current_file).unwrap_or_default()
+    }
+
+    pub fn play_count(&self, entry: &FileEntry) -> u32 {
+        self.play_counts
+            .get(&entry.path)
+            .copied()
+            .unwrap_or_default()
+    }
+
     pub fn update_paths(&mut self, new_working_paths: &VecDeque<WorkingPath>) {
         let new_paths = new_working_paths
             .iter()
@@ -139,37 +335,61 @@ impl Files {
             .for_each(|path| match self.watcher.unwatch(path) {
                 Ok(_) | Err(_) => {}
             });
-        unwrapped_path.iter().for_each(|path| {
+        new_paths.iter().for_each(|path| {
             match self.watcher.watch(path, RecursiveMode::Recursive) {
                 Ok(_) | Err(_) => {}
             }
         });
 
+        if let Some(archive_workers) = &*self.archive_workers.read().unwrap() {
+            archive_workers.scan(&new_paths);
+        }
         let mut unwrapped_path = paths.write().unwrap();
         *unwrapped_path = new_paths;
 
         self.line_index = 0;
     }
 
+    /// Follow directory and config changes.
     pub fn listen_start(&mut self) {
         let receiver = self.receiver.clone();
         let dir_changed = self.dir_changed.clone();
+        let config_changed = self.config_changed.clone();
+        let config_file = self.settings.config_file();
+        let archive_workers = self.archive_workers.clone();
         // Wait here for directory changes
         thread::spawn(move || loop {
             match receiver.recv() {
-                Ok(_event) => {
+                Ok(Ok(event)) => {
+                    if event
+                        .paths
+                        .iter()
+                        .any(|path| Some(path) == config_file.as_ref())
+                    {
+                        *config_changed.write().unwrap() = true;
+                        continue;
+                    }
+                    if let Some(archive_workers) = &*archive_workers.read().unwrap() {
+                        event
+                            .paths
+                            .iter()
+                            .for_each(|path| archive_workers.queue(path));
+                    }
                     // Send event resize instead
                     let mut dir_changed = dir_changed.write().unwrap();
                     *dir_changed = true;
                 }
-                Err(e) => println!("watch error: {:?}", e),
+                Ok(Err(e)) => log::error!("watch error: {:?}", e),
+                Err(e) => {
+                    log::error!("watch error: {:?}", e);
+                    break;
+                }
             };
         });
     }
 
-    pub fn date_to_color(created: SystemTime) -> (u8, u8, u8) {
+    pub fn date_to_color(created: SystemTime, gradient: colorous::Gradient) -> (u8, u8, u8) {
         let current_date = chrono::DateTime::<Utc>::from(created);
-        let gradient = colorous::RAINBOW;
 
         let start_of_year = NaiveDate::from_ymd_opt(current_date.year(), 1, 1)
             .unwrap_or(NaiveDate::MIN)
@@ -195,42 +415,23 @@ impl Files {
         gradient.eval_continuous(ratio).as_tuple()
     }
 
+    #[cfg_attr(not(feature = "mpv"), allow(unused_variables))]
     pub fn get_backend(&mut self, file_name: &str) -> &mut dyn AudioBackend {
-        if file_name.ends_with("opus") && cfg!(feature = "mpv") {
-            &mut self.mpv_client
-        } else {
-            &mut self.rodio_client
+        // Only Rodio reads archive members
+        #[cfg(feature = "mpv")]
+        if file_name.ends_with("opus") && archive::split_member(file_name).is_none() {
+            return self.mpv_client.as_mut();
         }
+        self.rodio_client.as_mut()
     }
 
-    pub fn handle_event(&mut self, frame: &Frame, chord: Chord) {
-        let chunks = crate::ui::get_chunks(frame);
-        let height = chunks[1].height;
+    /// Keys typed in the filter.
+    pub fn handle_event(&mut self, chord: Chord) {
         match chord.1 {
-            KeyCode::Down => self.lines_down(1),
-            KeyCode::Up => self.lines_up(1),
-            KeyCode::Enter => {
-                self.play_file();
-            }
-            KeyCode::PageDown => {
-                let slice_size_half = height / 2;
-                self.lines_down(slice_size_half as i32)
-            }
-            KeyCode::PageUp => {
-                let slice_size_half = height / 2;
-                self.lines_up(slice_size_half as i32)
-            }
             KeyCode::Char(c) => {
                 log::debug!("EVENT {:?}", c);
-                if c == 'n' && chord.0 == KeyModifiers::CONTROL {
-                    self.play_next();
-                } else if c == 'p' && chord.0 == KeyModifiers::CONTROL {
-                    self.lines_up(1);
-                    self.play_next();
-                } else {
-                    self.filter = format!("{}{}", self.filter, c);
-                    self.update_lines_filtered();
-                }
+                self.filter = format!("{}{}", self.filter, c);
+                self.update_lines_filtered();
             }
             KeyCode::Backspace => {
                 let mut chars = self.filter.chars();
@@ -267,33 +468,248 @@ impl Files {
             if current_backend.busy() {
                 current_backend.silent_pause();
             }
+            // Unsaved speeds are kept, unless leaving a directory with a saved one
+            match self.directory_speed(&new_file) {
+                Some(speed) if speed != self.speed => self.set_speed(speed),
+                None if self.directory_speed(&current_file).is_some() => self.set_speed(1.0),
+                _ => {}
+            }
+            if self.ab_loop != (None, None) {
+                self.clear_loop();
+            }
             let new_backend = self.get_backend(&new_file);
             new_backend.start(&new_file);
             self.current_file = new_file.clone();
+            self.queue_current_tags();
+            *self.play_counts.entry(new_file.clone()).or_default() += 1;
+            self.settings
+                .modify_state(|state| *state.play_counts.entry(new_file.clone()).or_default() += 1);
+            if let (Ok(cache_dir), Ok(data_dir)) = (
+                utils::config::get_cache_dir(),
+                utils::config::get_data_dir(),
+            ) {
+                archive_cache::touch(&cache_dir, &data_dir, &new_file);
+            }
         }
 
-        // Update currently playing file in Config
-        let config = utils::config::get_config();
-        utils::config::update_config(&Config {
-            current_file: self.current_file.clone(),
-            ..config
-        });
+        // Update currently playing file in State
+        self.settings
+            .modify_state(|state| state.current_file = self.current_file.clone());
 
         log::debug!("play_file {:?}", self.line_index);
     }
 
-    fn lines_down(&mut self, line_number: i32) {
+    /// Half a page down.
+    pub fn page_down(&mut self, frame: &Frame) {
+        let height = crate::ui::get_chunks(frame)[1].height;
+        self.lines_down((height / 2) as i32);
+    }
+
+    pub fn page_up(&mut self, frame: &Frame) {
+        let height = crate::ui::get_chunks(frame)[1].height;
+        self.lines_up((height / 2) as i32);
+    }
+
+    /// First shown, selected and past the last shown lines, for a list `height` high.
+    pub fn visible_lines(&self, height: u16) -> (usize, usize, usize) {
+        let (first, selected, end) = utils::get_borders(
+            self.lines_filtered.len() as isize,
+            height as isize + 1,
+            self.line_index as isize,
+        );
+        (first as usize, selected as usize, end as usize)
+    }
+
+    pub fn first_line(&mut self) {
+        self.line_index = 0;
+    }
+
+    pub fn last_line(&mut self) {
+        self.line_index = cmp::max(self.lines_filtered.len() as i32 - 1, 0);
+    }
+
+    /// Pause or resume the current file.
+    pub fn toggle_pause(&mut self) {
+        let current_file = self.current_file.clone();
+        if !current_file.is_empty() {
+            self.get_backend(&current_file).toggle();
+        }
+    }
+
+    /// Play the file before the selected one.
+    pub fn play_previous(&mut self) {
+        self.lines_up(1);
+        self.play_next();
+    }
+
+    pub fn lines_down(&mut self, line_number: i32) {
         let line_length: i32 = self.lines_filtered.len() as i32;
         log::debug!("play_file A {:?}", self.line_index);
         self.line_index = cmp::min(self.line_index + line_number, line_length - 1);
         log::debug!("play_file B {:?}", self.line_index);
     }
 
-    fn lines_up(&mut self, line_number: i32) {
+    pub fn lines_up(&mut self, line_number: i32) {
         self.line_index = cmp::max(self.line_index - line_number, 0);
     }
 
-    fn play_next(&mut self) {
+    pub fn switch_play_mode(&mut self) {
+        // TODO : variant_count, enumerate PlayModes
+        self.play_mode = match self.play_mode {
+            PlayMode::Queue => PlayMode::Random,
+            PlayMode::Random => PlayMode::Queue,
+        };
+
+        self.settings
+            .modify_state(|state| state.play_mode = self.play_mode);
+    }
+
+    pub fn cycle_sort(&mut self) {
+        self.sort = match self.sort {
+            Sort::Newest => Sort::Oldest,
+            Sort::Oldest => Sort::Name,
+            Sort::Name => Sort::Newest,
+        };
+        self.settings.modify_state(|state| state.sort = self.sort);
+        self.update_lines();
+        self.update_lines_filtered();
+    }
+
+    /// Move to the line of the file playing, if it is listed.
+    pub fn jump_to_playing(&mut self) {
+        let current_file = self.current_file.clone();
+        self.line_index = self.get_line_index(&current_file);
+    }
+
+    /// Switch to the next output device, the default one coming first.
+    pub fn cycle_output_device(&mut self) {
+        let devices = backend_rodio::output_devices();
+        let next = match &self.output_device {
+            None => devices.first().cloned(),
+            Some(current) => devices
+                .iter()
+                .skip_while(|device| *device != current)
+                .nth(1)
+                .cloned(),
+        };
+        self.set_output_device(next);
+    }
+
+    pub fn set_output_device(&mut self, device: Option<String>) {
+        log::info!("output device {:?}", device);
+        #[cfg(feature = "mpv")]
+        self.mpv_client.set_device(device.as_deref());
+        self.rodio_client.set_device(device.as_deref());
+        self.output_device = device;
+
+        self.settings
+            .modify_config(|config| config.output_device = self.output_device.clone());
+    }
+
+    /// Speed saved for the closest directory of `file_name`.
+    fn directory_speed(&self, file_name: &str) -> Option<f32> {
+        self.speeds
+            .iter()
+            .filter(|(directory, _)| Path::new(file_name).starts_with(directory))
+            .max_by_key(|(directory, _)| directory.len())
+            .map(|(_, speed)| *speed)
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        // Avoid drifting away from round speeds
+        self.speed = (speed.clamp(MIN_SPEED, MAX_SPEED) * 100.0).round() / 100.0;
+        log::debug!("speed {}", self.speed);
+        #[cfg(feature = "mpv")]
+        self.mpv_client.set_speed(self.speed);
+        self.rodio_client.set_speed(self.speed);
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = (volume.clamp(0.0, 1.0) * 100.0).round() / 100.0;
+        self.apply_volume();
+        self.settings
+            .modify_state(|state| state.volume = self.volume);
+    }
+
+    fn apply_volume(&mut self) {
+        #[cfg(feature = "mpv")]
+        self.mpv_client.set_volume(self.volume);
+        self.rodio_client.set_volume(self.volume);
+    }
+
+    /// Always play the directory of the current file at the current speed.
+    pub fn save_speed(&mut self) {
+        let Some(directory) = Path::new(&self.current_file).parent() else {
+            return;
+        };
+        let directory = directory.to_string_lossy().to_string();
+        if self.speed == 1.0 {
+            self.speeds.remove(&directory);
+        } else {
+            self.speeds.insert(directory, self.speed);
+        }
+
+        self.settings
+            .modify_config(|config| config.speeds = self.speeds.clone());
+    }
+
+    pub fn song_state(&mut self) -> SongState {
+        let current_file = self.current_file.clone();
+        self.get_backend(&current_file).state()
+    }
+
+    pub fn position(&mut self) -> Duration {
+        let current_file = self.current_file.clone();
+        self.get_backend(&current_file).position()
+    }
+
+    pub fn seek(&mut self, position: Duration) {
+        let current_file = self.current_file.clone();
+        self.get_backend(&current_file).seek(position);
+    }
+
+    pub fn set_loop_a(&mut self) {
+        let a = self.position();
+        let b = self.ab_loop.1.filter(|b| *b > a);
+        self.ab_loop = (Some(a), b);
+        self.apply_loop();
+    }
+
+    pub fn set_loop_b(&mut self) {
+        let b = self.position();
+        let a = self.ab_loop.0.filter(|a| *a < b);
+        self.ab_loop = (a.or(Some(Duration::ZERO)), Some(b));
+        self.apply_loop();
+    }
+
+    pub fn clear_loop(&mut self) {
+        self.ab_loop = (None, None);
+        self.apply_loop();
+    }
+
+    fn apply_loop(&mut self) {
+        let ab_loop = match self.ab_loop {
+            (Some(a), Some(b)) => Some((a, b)),
+            _ => None,
+        };
+        let current_file = self.current_file.clone();
+        self.get_backend(&current_file).set_loop(ab_loop);
+    }
+
+    /// Lines played next in queue mode, none in random mode.
+    pub fn up_next(&self) -> &[FileLine] {
+        let lines = &self.lines_filtered;
+        let index = self.line_index as usize;
+        let start = match lines.get(index) {
+            _ if self.play_mode == PlayMode::Random => return &[],
+            // From the selected line when the cursor moved away
+            Some(line) if line.dir_entry.path().to_str() == Some(&self.current_file) => index + 1,
+            _ => index,
+        };
+        lines.get(start..).unwrap_or_default()
+    }
+
+    pub fn play_next(&mut self) {
         let index_moved;
         {
             let lines = &self.lines_filtered;
@@ -313,13 +729,12 @@ impl Files {
 
         if !index_moved {
             log::debug!("!index_moved");
-            let config = utils::config::get_config();
-            match config.play_mode {
-                utils::config::PlayMode::Queue => {
+            match self.play_mode {
+                PlayMode::Queue => {
                     self.lines_down(1); //
                     log::debug!("lines_down Queue");
                 }
-                utils::config::PlayMode::Random => {
+                PlayMode::Random => {
                     let line_length: i32 = self.lines_filtered.len() as i32;
                     // let r = 0..(line_length - 1);
                     let mut rng = thread_rng();
@@ -426,16 +841,101 @@ impl Files {
 
     pub fn startup(&mut self) {}
 
+    /// Whether the config file changed since the last call.
+    pub fn take_config_changed(&self) -> bool {
+        std::mem::take(&mut *self.config_changed.write().unwrap())
+    }
+
+    /// Follow the config, edited while playing. Working paths are updated apart.
+    pub fn apply_config(&mut self, config: &Config) {
+        let archives_changed = self.extensions_archives != config.extensions_archives
+            || self.archive_mode != config.archive_mode
+            || self.archive_cache_max_mb != config.archive_cache_max_mb
+            || self.archive_passwords != config.archive_passwords
+            || self.nested_archive_depth != config.nested_archive_depth;
+        self.extensions = config.extensions.clone();
+        self.extensions_archives = config.extensions_archives.clone();
+        self.archive_mode = config.archive_mode;
+        self.archive_cache_max_mb = config.archive_cache_max_mb;
+        self.archive_passwords = config.archive_passwords.clone();
+        self.nested_archive_depth = config.nested_archive_depth;
+        self.speeds = config.speeds.clone();
+        if self.output_device != config.output_device {
+            #[cfg(feature = "mpv")]
+            self.mpv_client.set_device(config.output_device.as_deref());
+            self.rodio_client
+                .set_device(config.output_device.as_deref());
+            self.output_device = config.output_device.clone();
+        }
+
+        // Workers in progress finish their archive
+        if archives_changed && self.archive_workers.read().unwrap().is_some() {
+            self.watch_archives();
+        }
+    }
+
+    /// Archive extensions listed in place rather than extracted.
+    fn virtual_extensions_archives(&self) -> Vec<String> {
+        match self.archive_mode {
+            ArchiveMode::Extract => vec![],
+            ArchiveMode::Virtual => self
+                .extensions_archives
+                .iter()
+                .filter(|extension| *extension == "zip")
+                .cloned()
+                .collect_vec(),
+        }
+    }
+
+    /// Extract the archives of the working paths, and those of directory changes.
     pub fn watch_archives(&mut self) {
+        let dirs = utils::config::get_cache_dir()
+            .and_then(|cache_dir| Ok((cache_dir, utils::config::get_data_dir()?)));
+        let (cache_dir, data_dir) = match dirs {
+            Ok(dirs) => dirs,
+            Err(e) => {
+                log::error!("Archives are not extracted: {e}");
+                return;
+            }
+        };
         let paths = self.paths.read().unwrap().clone();
-        let extensions_archives = self.extensions_archives.clone();
-        let cache_dir = utils::config::get_cache_dir();
+        let virtual_extensions = self.virtual_extensions_archives();
+        let extensions_archives = self
+            .extensions_archives
+            .iter()
+            .filter(|extension| !virtual_extensions.contains(extension))
+            .cloned()
+            .collect_vec();
 
-        thread::spawn(move || loop {
-            if poll(Duration::from_millis(1000)).unwrap() {
-                utils::directory::process_archives(&paths, &extensions_archives, &cache_dir);
-            }
-        });
+        let options = ArchiveOptions {
+            extensions_archives,
+            max_depth: self.nested_archive_depth,
+            max_size: self.archive_cache_max_mb.map(|mb| mb * 1024 * 1024),
+            passwords: RwLock::new(self.archive_passwords.clone()),
+        };
+        let archive_workers = ArchiveWorkers::start(
+            ARCHIVE_WORKERS,
+            options,
+            cache_dir,
+            &data_dir,
+            self.archive_status.clone(),
+        );
+        archive_workers.scan(&paths);
+        *self.archive_workers.write().unwrap() = Some(archive_workers);
+    }
+
+    /// Remove every extracted archive, extracted again when it changes or on the next start.
+    pub fn clear_archive_cache(&self) {
+        if let Some(archive_workers) = &*self.archive_workers.read().unwrap() {
+            archive_workers.clear_cache();
+        }
+    }
+
+    /// Extract an encrypted archive with the password typed in.
+    pub fn unlock_archive(&self, archive: &Path, password: &str) {
+        if let Some(archive_workers) = &*self.archive_workers.read().unwrap() {
+            archive_workers.unlock(archive, password);
+        }
     }
 
     pub fn on_tick(&mut self) {
@@ -453,3 +953,463 @@ impl Files {
         // let paths = self.paths.read().unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend_null::Call;
+    use crate::columns;
+    use crate::keymap::Preset;
+    use crate::utils::config::PlayMode;
+    use crate::utils::state::State;
+    use std::fs::File;
+    use tempfile::TempDir;
+
+    struct Setup {
+        files: Files,
+        null: Null,
+        music: TempDir,
+        // Shared with files, saved nowhere
+        settings: Settings,
+    }
+
+    impl Setup {
+        fn path(&self, name: &str) -> String {
+            self.music.path().join(name).to_string_lossy().to_string()
+        }
+
+        fn file_names(&self) -> Vec<String> {
+            self.files
+                .lines_filtered
+                .iter()
+                .map(|line| line.dir_entry.file_name().to_string_lossy().to_string())
+                .collect_vec()
+        }
+
+        fn end_song(&mut self) {
+            self.null.advance(Duration::from_secs(3600));
+            self.files.on_tick();
+        }
+    }
+
+    /// Files are created in order, so they are listed in reverse.
+    fn setup(play_mode: PlayMode, names: &[&str]) -> Setup {
+        let music = TempDir::new().unwrap();
+        for name in names {
+            File::create(music.path().join(name)).unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let config = Config {
+            extensions: vec![String::from("mp3"), String::from("opus")],
+            extensions_archives: vec![],
+            version: utils::config::CONFIG_VERSION,
+            tick_rate: 500,
+            working_directories: VecDeque::from([WorkingPath {
+                path: music.path().to_string_lossy().to_string(),
+                status: Status::Active,
+            }]),
+            log_level: log::LevelFilter::Off,
+            backend: Backend::Null,
+            output_device: None,
+            speeds: HashMap::new(),
+            archive_mode: ArchiveMode::Extract,
+            archive_cache_max_mb: None,
+            archive_passwords: HashMap::new(),
+            nested_archive_depth: 0,
+            keymap_preset: Preset::Default,
+            keybindings: HashMap::new(),
+            theme: String::from("dark"),
+            themes: HashMap::new(),
+            columns: columns::default_columns(),
+            date_format: columns::default_date_format(),
+        };
+
+        let null = Null::manual();
+        let state = State {
+            play_mode,
+            ..State::default()
+        };
+        let settings = Settings::in_memory(config, state);
+        let mut files = Files::new(settings.clone());
+        files.set_backend(null.clone());
+        files.update_lines();
+        files.update_lines_filtered();
+
+        Setup {
+            files,
+            null,
+            music,
+            settings,
+        }
+    }
+
+    #[test]
+    fn lines_are_sorted_by_creation_and_extension() {
+        let setup = setup(PlayMode::Queue, &["a.mp3", "notes.txt", "b.opus", "c.mp3"]);
+        assert_eq!(setup.file_names(), vec!["c.mp3", "b.opus", "a.mp3"]);
+    }
+
+    #[test]
+    fn sort_order_is_cycled_and_saved() {
+        let mut setup = setup(PlayMode::Queue, &["b.mp3", "c.mp3", "a.mp3"]);
+        setup.files.cycle_sort();
+        assert_eq!(setup.file_names(), vec!["b.mp3", "c.mp3", "a.mp3"]);
+        setup.files.cycle_sort();
+        assert_eq!(setup.file_names(), vec!["a.mp3", "b.mp3", "c.mp3"]);
+        assert_eq!(setup.settings.state().sort, Sort::Name);
+    }
+
+    #[test]
+    fn up_next_follows_the_cursor() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3", "b.mp3", "c.mp3"]);
+        let up_next = |files: &Files| {
+            files
+                .up_next()
+                .iter()
+                .map(|line| line.dir_entry.file_name().to_string_lossy().to_string())
+                .collect_vec()
+        };
+        setup.files.play_file();
+        assert_eq!(up_next(&setup.files), vec!["b.mp3", "a.mp3"]);
+        setup.files.lines_down(1);
+        assert_eq!(up_next(&setup.files), vec!["b.mp3", "a.mp3"]);
+        setup.files.lines_down(1);
+        assert_eq!(up_next(&setup.files), vec!["a.mp3"]);
+
+        setup.files.switch_play_mode();
+        assert!(up_next(&setup.files).is_empty());
+    }
+
+    #[test]
+    fn volume_is_bounded_and_saved() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3"]);
+        setup.files.set_volume(1.2);
+        assert_eq!(setup.files.volume, 1.0);
+        setup.files.set_volume(0.449);
+        assert_eq!(setup.null.calls().last(), Some(&Call::SetVolume(0.45)));
+        assert_eq!(setup.settings.state().volume, 0.45);
+    }
+
+    #[test]
+    fn queue_plays_next_song_when_song_ends() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3", "b.mp3", "c.mp3"]);
+        setup.files.play_file();
+        setup.end_song();
+        setup.end_song();
+
+        assert_eq!(
+            setup.null.calls(),
+            vec![
+                Call::Start(setup.path("c.mp3")),
+                Call::Start(setup.path("b.mp3")),
+                Call::Start(setup.path("a.mp3")),
+            ]
+        );
+        assert_eq!(setup.files.line_index, 2);
+    }
+
+    #[test]
+    fn queue_does_not_restart_last_song() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3", "b.mp3"]);
+        setup.files.line_index = 1;
+        setup.files.play_file();
+        setup.null.clear_calls();
+        setup.end_song();
+
+        assert_eq!(setup.null.calls(), vec![Call::Toggle]);
+        assert_eq!(setup.null.state(), SongState::Ended);
+    }
+
+    #[test]
+    fn autoplay_waits_until_song_ends() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3", "b.mp3"]);
+        setup
+            .null
+            .set_duration(&setup.path("b.mp3"), Duration::from_secs(10));
+        setup.files.play_file();
+        setup.null.advance(Duration::from_secs(9));
+        setup.files.on_tick();
+        assert_eq!(setup.null.calls(), vec![Call::Start(setup.path("b.mp3"))]);
+
+        setup.null.advance(Duration::from_secs(1));
+        setup.files.on_tick();
+        assert_eq!(
+            setup.null.calls(),
+            vec![
+                Call::Start(setup.path("b.mp3")),
+                Call::Start(setup.path("a.mp3")),
+            ]
+        );
+    }
+
+    #[test]
+    fn paused_song_is_not_autoplayed() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3", "b.mp3"]);
+        setup.files.play_file();
+        // Playing the current line again toggles it
+        setup.files.play_file();
+        setup.end_song();
+
+        assert_eq!(
+            setup.null.calls(),
+            vec![Call::Start(setup.path("b.mp3")), Call::Toggle]
+        );
+        assert_eq!(setup.null.state(), SongState::Paused);
+    }
+
+    #[test]
+    fn playing_another_line_pauses_current_song() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3", "b.mp3", "c.mp3"]);
+        setup.files.play_file();
+        setup.files.lines_down(2);
+        setup.files.play_file();
+
+        assert_eq!(
+            setup.null.calls(),
+            vec![
+                Call::Start(setup.path("c.mp3")),
+                Call::SilentPause,
+                Call::Start(setup.path("a.mp3")),
+            ]
+        );
+        assert_eq!(setup.files.current_file, setup.path("a.mp3"));
+    }
+
+    #[test]
+    fn play_counts_are_saved_but_not_on_toggle() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3", "b.mp3", "c.mp3"]);
+        setup.files.play_file();
+        // Pauses
+        setup.files.play_file();
+        setup.files.lines_down(2);
+        setup.files.play_file();
+        setup.files.lines_up(2);
+        setup.files.play_file();
+
+        let lines = setup.files.lines_filtered.clone();
+        assert_eq!(setup.files.play_count(&lines[0].dir_entry), 2);
+        assert_eq!(setup.files.play_count(&lines[1].dir_entry), 0);
+        assert_eq!(setup.files.play_count(&lines[2].dir_entry), 1);
+        assert_eq!(
+            setup.settings.state().play_counts,
+            HashMap::from([(setup.path("c.mp3"), 2), (setup.path("a.mp3"), 1)])
+        );
+    }
+
+    #[test]
+    fn random_plays_another_listed_song() {
+        let names = ["a.mp3", "b.mp3", "c.mp3", "d.mp3", "e.mp3"];
+        let mut setup = setup(PlayMode::Random, &names);
+        setup.files.play_file();
+        setup.end_song();
+
+        let calls = setup.null.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0], Call::Start(setup.path("e.mp3")));
+        let Call::Start(next) = &calls[1] else {
+            panic!("expected a started song, got {:?}", calls[1]);
+        };
+        assert_ne!(*next, setup.path("e.mp3"));
+        assert!(names.iter().any(|name| setup.path(name) == *next));
+        assert_eq!(setup.files.current_file, *next);
+    }
+
+    #[test]
+    fn filter_narrows_lines_and_keeps_current_song_selected() {
+        let mut setup = setup(
+            PlayMode::Queue,
+            &["blue.mp3", "red.mp3", "black.mp3", "green.mp3"],
+        );
+        setup.files.line_index = 1;
+        setup.files.play_file();
+
+        setup.files.filter = String::from("bl");
+        setup.files.update_lines_filtered();
+
+        assert_eq!(setup.file_names(), vec!["black.mp3", "blue.mp3"]);
+        assert_eq!(setup.files.line_index, 0);
+        assert_eq!(setup.files.current_file, setup.path("black.mp3"));
+    }
+
+    #[test]
+    fn queue_follows_filtered_lines() {
+        let mut setup = setup(
+            PlayMode::Queue,
+            &["blue.mp3", "red.mp3", "black.mp3", "green.mp3"],
+        );
+        setup.files.filter = String::from("bl");
+        setup.files.update_lines_filtered();
+        setup.files.play_file();
+        setup.end_song();
+
+        assert_eq!(
+            setup.null.calls(),
+            vec![
+                Call::Start(setup.path("black.mp3")),
+                Call::Start(setup.path("blue.mp3")),
+            ]
+        );
+    }
+
+    #[test]
+    fn output_device_is_switched_and_saved() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3"]);
+        setup
+            .files
+            .set_output_device(Some(String::from("hw:CARD=DAC,DEV=0")));
+
+        assert!(setup
+            .null
+            .calls()
+            .contains(&Call::SetDevice(Some(String::from("hw:CARD=DAC,DEV=0")))));
+        assert_eq!(
+            setup.settings.config().output_device,
+            Some(String::from("hw:CARD=DAC,DEV=0"))
+        );
+    }
+
+    #[test]
+    fn saved_directory_speed_is_applied() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3", "b.mp3"]);
+        setup
+            .null
+            .set_duration(&setup.path("b.mp3"), Duration::from_secs(15));
+        setup.files.play_file();
+        setup.files.set_speed(1.5);
+        setup.files.save_speed();
+        let directory = setup.music.path().to_string_lossy().to_string();
+        assert_eq!(
+            setup.settings.config().speeds,
+            HashMap::from([(directory.clone(), 1.5)])
+        );
+
+        // Songs of the directory start at the saved speed
+        setup.files.set_speed(1.0);
+        setup.files.speeds = HashMap::from([(directory, 1.5)]);
+        setup.files.current_file = String::default();
+        setup.null.clear_calls();
+        setup.files.play_file();
+        let calls = setup.null.calls();
+        assert!(calls.contains(&Call::SetSpeed(1.5)));
+        assert_eq!(calls.last(), Some(&Call::Start(setup.path("b.mp3"))));
+
+        setup.null.advance(Duration::from_secs(10));
+        assert_eq!(setup.null.state(), SongState::Ended);
+    }
+
+    #[test]
+    fn unsaved_speed_is_kept_for_the_next_song() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3", "b.mp3"]);
+        setup.files.play_file();
+        setup.files.set_speed(1.5);
+        setup.files.lines_down(1);
+        setup.files.play_file();
+        assert_eq!(setup.files.speed, 1.5);
+
+        // Until leaving a directory with a saved speed
+        let directory = setup.music.path().to_string_lossy().to_string();
+        setup.files.speeds = HashMap::from([(directory, 1.25)]);
+        setup.files.lines_up(1);
+        setup.files.play_file();
+        assert_eq!(setup.files.speed, 1.25);
+        let elsewhere = TempDir::new().unwrap();
+        setup.files.lines_filtered[1].dir_entry.path =
+            elsewhere.path().join("c.mp3").to_string_lossy().to_string();
+        setup.files.lines_down(1);
+        setup.files.play_file();
+        assert_eq!(setup.files.speed, 1.0);
+    }
+
+    #[test]
+    fn speed_is_clamped() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3"]);
+        setup.files.set_speed(10.0);
+        assert_eq!(setup.files.speed, MAX_SPEED);
+        setup.files.set_speed(0.1);
+        assert_eq!(setup.files.speed, MIN_SPEED);
+        setup.files.set_speed(1.0 + 0.1 + 0.1 + 0.1);
+        assert_eq!(setup.files.speed, 1.3);
+    }
+
+    #[test]
+    fn ab_loop_repeats_until_cleared() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3", "b.mp3"]);
+        setup
+            .null
+            .set_duration(&setup.path("b.mp3"), Duration::from_secs(60));
+        setup.files.play_file();
+        setup.null.advance(Duration::from_secs(10));
+        setup.files.set_loop_a();
+        setup.null.advance(Duration::from_secs(5));
+        setup.files.set_loop_b();
+        assert_eq!(
+            setup.files.ab_loop,
+            (Some(Duration::from_secs(10)), Some(Duration::from_secs(15)))
+        );
+
+        for _ in 0..20 {
+            setup.null.advance(Duration::from_secs(3));
+            setup.files.on_tick();
+            let position = setup.files.position();
+            assert!(position >= Duration::from_secs(10) && position < Duration::from_secs(15));
+        }
+
+        setup.files.clear_loop();
+        setup.end_song();
+        assert_eq!(setup.files.current_file, setup.path("a.mp3"));
+    }
+
+    #[test]
+    fn loop_is_cleared_on_next_song() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3", "b.mp3"]);
+        setup.files.play_file();
+        setup.null.advance(Duration::from_secs(10));
+        setup.files.set_loop_b();
+        assert_eq!(
+            setup.files.ab_loop,
+            (Some(Duration::ZERO), Some(Duration::from_secs(10)))
+        );
+
+        setup.files.lines_down(1);
+        setup.files.play_file();
+        assert_eq!(setup.files.ab_loop, (None, None));
+        assert!(setup.null.calls().contains(&Call::SetLoop(None)));
+    }
+
+    #[test]
+    fn nothing_is_played_without_lines() {
+        let mut setup = setup(PlayMode::Queue, &[]);
+        setup.files.play_file();
+        setup.end_song();
+
+        assert!(setup.null.calls().is_empty());
+    }
+
+    #[test]
+    fn zip_members_are_listed_and_played_in_virtual_mode() {
+        let mut setup = setup(PlayMode::Queue, &["a.mp3"]);
+        let archive = setup.music.path().join("album.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&archive).unwrap());
+        for name in ["disc1/01.mp3", "notes.txt"] {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        setup.files.update_lines();
+        setup.files.update_lines_filtered();
+        assert_eq!(setup.file_names(), ["a.mp3"]);
+
+        setup.files.archive_mode = ArchiveMode::Virtual;
+        setup.files.extensions_archives = vec![String::from("zip")];
+        setup.files.update_lines();
+        setup.files.update_lines_filtered();
+        assert_eq!(setup.file_names(), ["album.zip!/disc1/01.mp3", "a.mp3"]);
+
+        setup.files.play_file();
+        let member = archive::member_path(&archive, "disc1/01.mp3");
+        assert_eq!(setup.null.calls(), [Call::Start(member)]);
+    }
+}
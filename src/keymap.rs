@@ -0,0 +1,721 @@
+use crate::app::Chord;
+use crossterm::event::{KeyCode, KeyModifiers};
+use itertools::Itertools;
+use serde_derive::{Deserialize, Serialize};
+use std::cmp;
+use std::collections::{BTreeSet, HashMap};
+use strum::{Display, EnumIter, EnumMessage, EnumString};
+
+// Keys are bound to actions in contexts, looked up from the most specific
+// context of the current tab to the global one. Bindings may be sequences of
+// chords, like "g g", the chords of an unfinished sequence are kept until it
+// completes or fails.
+
+#[derive(
+    Serialize, Deserialize, Display, EnumIter, EnumString, Clone, Copy, PartialEq, Eq, Hash, Debug,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Context {
+    Global,
+    Directories,
+    Files,
+    Bookmarks,
+    /// Keys acting on the current song, in Files and Bookmarks
+    Playback,
+    /// Files out of the filter, with the vim preset
+    Normal,
+    /// Files while the filter is typed, with the vim preset
+    Filter,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    /// Characters are typed in the filter, shortcuts use ctrl
+    #[default]
+    Default,
+    /// Letters are shortcuts in the normal mode of Files, / starts the filter
+    Vim,
+}
+
+/// Modes of Files, with the vim preset.
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    #[default]
+    Normal,
+    Filter,
+}
+
+/// Messages are the descriptions shown in the help.
+#[derive(
+    Serialize,
+    Deserialize,
+    Display,
+    EnumIter,
+    EnumMessage,
+    EnumString,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Debug,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Action {
+    #[strum(message = "Quit")]
+    Quit,
+    #[strum(message = "Go to the next tab")]
+    NextTab,
+    #[strum(message = "Move up")]
+    Up,
+    #[strum(message = "Move down")]
+    Down,
+    #[strum(message = "Move up half a page")]
+    PageUp,
+    #[strum(message = "Move down half a page")]
+    PageDown,
+    #[strum(message = "Play the file, add the directory, jump to or add the bookmark")]
+    Select,
+    #[strum(message = "Remove the directory or the bookmark")]
+    Delete,
+    #[strum(message = "Go to the first line")]
+    FirstLine,
+    #[strum(message = "Go to the last line")]
+    LastLine,
+    #[strum(message = "Play the next file")]
+    PlayNext,
+    #[strum(message = "Play the previous file")]
+    PlayPrevious,
+    #[strum(message = "Pause or resume")]
+    TogglePause,
+    #[strum(message = "Type in the filter")]
+    StartFilter,
+    #[strum(message = "Leave the filter")]
+    StopFilter,
+    #[strum(message = "Switch the play mode")]
+    SwitchPlayMode,
+    #[strum(message = "Use the next output device")]
+    CycleOutputDevice,
+    #[strum(message = "Play faster")]
+    SpeedUp,
+    #[strum(message = "Play slower")]
+    SpeedDown,
+    #[strum(message = "Keep the speed for this directory")]
+    SaveSpeed,
+    #[strum(message = "Start the loop here")]
+    SetLoopA,
+    #[strum(message = "End the loop here")]
+    SetLoopB,
+    #[strum(message = "Stop looping")]
+    ClearLoop,
+    #[strum(message = "Show or hide the keys")]
+    Help,
+    #[strum(message = "Search the actions")]
+    CommandPalette,
+    #[strum(message = "Add a directory")]
+    AddDirectory,
+    #[strum(message = "Deactivate or activate the directory")]
+    ToggleDirectory,
+    #[strum(message = "Remove the extracted archives")]
+    ClearCache,
+    #[strum(message = "Change the order of the files")]
+    CycleSort,
+    #[strum(message = "Jump to the file playing")]
+    JumpToPlaying,
+    #[strum(message = "Play louder")]
+    VolumeUp,
+    #[strum(message = "Play quieter")]
+    VolumeDown,
+}
+
+impl Action {
+    pub fn description(self) -> &'static str {
+        self.get_message().unwrap_or_default()
+    }
+}
+
+/// Keys of actions by context, as written in the config, replacing the
+/// default keys of these actions. Unknown names are reported by the keymap.
+pub type Bindings = HashMap<String, HashMap<String, Vec<String>>>;
+
+const DEFAULT_BINDINGS: &[(Context, Action, &str)] = &[
+    (Context::Global, Action::Quit, "ctrl-c"),
+    (Context::Global, Action::Quit, "ctrl-d"),
+    (Context::Global, Action::Help, "f1"),
+    // Reported as ctrl-p by terminals without the kitty keyboard protocol
+    (Context::Global, Action::CommandPalette, "ctrl-shift-p"),
+    (Context::Global, Action::CommandPalette, "alt-x"),
+    // Typed in the filter and the bookmark label elsewhere
+    (Context::Directories, Action::Help, "?"),
+    (Context::Directories, Action::NextTab, "tab"),
+    (Context::Directories, Action::Up, "up"),
+    (Context::Directories, Action::Down, "down"),
+    (Context::Directories, Action::Delete, "k"),
+    (Context::Files, Action::NextTab, "tab"),
+    (Context::Files, Action::Up, "up"),
+    (Context::Files, Action::Down, "down"),
+    (Context::Files, Action::PageUp, "pageup"),
+    (Context::Files, Action::PageDown, "pagedown"),
+    (Context::Files, Action::Select, "enter"),
+    (Context::Files, Action::PlayNext, "ctrl-n"),
+    (Context::Files, Action::PlayPrevious, "ctrl-p"),
+    (Context::Bookmarks, Action::NextTab, "tab"),
+    (Context::Bookmarks, Action::Up, "up"),
+    (Context::Bookmarks, Action::Down, "down"),
+    (Context::Bookmarks, Action::Select, "enter"),
+    (Context::Bookmarks, Action::Delete, "delete"),
+    (Context::Playback, Action::SwitchPlayMode, "ctrl-t"),
+    (Context::Playback, Action::CycleOutputDevice, "ctrl-o"),
+    (Context::Playback, Action::SpeedUp, "ctrl-right"),
+    (Context::Playback, Action::SpeedDown, "ctrl-left"),
+    (Context::Playback, Action::SaveSpeed, "ctrl-s"),
+    (Context::Playback, Action::SetLoopA, "ctrl-a"),
+    (Context::Playback, Action::SetLoopB, "ctrl-b"),
+    (Context::Playback, Action::ClearLoop, "ctrl-l"),
+    (Context::Playback, Action::VolumeUp, "ctrl-up"),
+    (Context::Playback, Action::VolumeDown, "ctrl-down"),
+    (Context::Playback, Action::JumpToPlaying, "ctrl-g"),
+];
+
+/// Added to the default bindings by the vim preset, but for ctrl-d, which
+/// pages down instead of quitting.
+const VIM_BINDINGS: &[(Context, Action, &str)] = &[
+    (Context::Normal, Action::Down, "j"),
+    (Context::Normal, Action::Up, "k"),
+    (Context::Normal, Action::FirstLine, "g g"),
+    (Context::Normal, Action::LastLine, "G"),
+    (Context::Normal, Action::PageDown, "ctrl-d"),
+    (Context::Normal, Action::PageUp, "ctrl-u"),
+    (Context::Normal, Action::PlayNext, "n"),
+    (Context::Normal, Action::PlayPrevious, "p"),
+    (Context::Normal, Action::TogglePause, "space"),
+    (Context::Normal, Action::StartFilter, "/"),
+    (Context::Normal, Action::Help, "?"),
+    (Context::Filter, Action::StopFilter, "esc"),
+    (Context::Filter, Action::StopFilter, "enter"),
+    (Context::Filter, Action::Up, "up"),
+    (Context::Filter, Action::Down, "down"),
+];
+
+/// Contexts looked up together, for conflicts.
+const STACKS: &[&[Context]] = &[
+    &[Context::Directories, Context::Global],
+    &[Context::Files, Context::Playback, Context::Global],
+    &[Context::Bookmarks, Context::Playback, Context::Global],
+    &[
+        Context::Normal,
+        Context::Files,
+        Context::Playback,
+        Context::Global,
+    ],
+    &[Context::Filter, Context::Global],
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Resolved {
+    Action(Action),
+    /// The chord starts a sequence
+    Pending,
+    /// Left to text input
+    Unbound,
+}
+
+struct Binding {
+    context: Context,
+    keys: Vec<Chord>,
+    action: Action,
+}
+
+pub struct Keymap {
+    pub preset: Preset,
+    bindings: Vec<Binding>,
+    // Chords of the sequence being typed, as looked up and as typed
+    pending: Vec<Chord>,
+    typed: Vec<Chord>,
+    /// Unknown names and keys, and conflicting bindings, the first binding wins
+    pub errors: Vec<String>,
+}
+
+impl Keymap {
+    pub fn new(preset: Preset, overrides: &Bindings) -> Keymap {
+        let mut errors = vec![];
+        let mut parsed: HashMap<Context, HashMap<Action, &Vec<String>>> = HashMap::new();
+        // Sorted for errors and conflicts to come the same way every time
+        for (context_name, actions) in overrides.iter().sorted_by_key(|(name, _)| *name) {
+            let Ok(context) = context_name.parse::<Context>() else {
+                errors.push(format!("Unknown context {context_name}"));
+                continue;
+            };
+            for (action_name, keys) in actions.iter().sorted_by_key(|(name, _)| *name) {
+                match action_name.parse::<Action>() {
+                    Ok(action) => {
+                        parsed.entry(context).or_default().insert(action, keys);
+                    }
+                    Err(_) => errors.push(format!("Unknown action {action_name} in {context}")),
+                }
+            }
+        }
+        let overrides = parsed;
+        let defaults = match preset {
+            Preset::Default => DEFAULT_BINDINGS.to_vec(),
+            Preset::Vim => DEFAULT_BINDINGS
+                .iter()
+                .filter(|binding| **binding != (Context::Global, Action::Quit, "ctrl-d"))
+                .chain(VIM_BINDINGS)
+                .cloned()
+                .collect(),
+        };
+        let mut bindings = defaults
+            .iter()
+            .filter(|(context, action, _)| {
+                !overrides
+                    .get(context)
+                    .is_some_and(|actions| actions.contains_key(action))
+            })
+            .map(|&(context, action, keys)| Binding {
+                context,
+                keys: parse_keys(keys).unwrap(),
+                action,
+            })
+            .collect_vec();
+
+        let overrides = overrides
+            .iter()
+            .flat_map(|(context, actions)| {
+                actions.iter().flat_map(move |(action, keys)| {
+                    keys.iter().map(move |keys| (*context, *action, keys))
+                })
+            })
+            .sorted_by_key(|(context, action, keys)| (*context as u8, *action as u8, *keys));
+        for (context, action, keys) in overrides {
+            match parse_keys(keys) {
+                Ok(keys) => bindings.push(Binding {
+                    context,
+                    keys,
+                    action,
+                }),
+                Err(e) => errors.push(format!("{context} {action}: {e}")),
+            }
+        }
+
+        errors.extend(conflicts(&bindings));
+        Keymap {
+            preset,
+            bindings,
+            pending: vec![],
+            typed: vec![],
+            errors,
+        }
+    }
+
+    /// Action of `chord` following the pending chords, looked up in `contexts` in order,
+    /// with the chords of a failed sequence before it, left to text input.
+    pub fn resolve(&mut self, contexts: &[Context], chord: Chord) -> (Vec<Chord>, Resolved) {
+        let mut keys = std::mem::take(&mut self.pending);
+        keys.push(normalize(chord));
+        let resolved = self.lookup(contexts, &keys);
+        match resolved {
+            Resolved::Pending => {
+                self.pending = keys;
+                self.typed.push(chord);
+            }
+            // Its last chord may start another
+            Resolved::Unbound if keys.len() > 1 => {
+                let unconsumed = std::mem::take(&mut self.typed);
+                return (unconsumed, self.resolve(contexts, chord).1);
+            }
+            _ => self.typed.clear(),
+        }
+        (vec![], resolved)
+    }
+
+    /// Bindings in effect in `contexts`, as (context, keys, action), without
+    /// those hidden by a more specific context.
+    pub fn active(&self, contexts: &[Context]) -> Vec<(Context, String, Action)> {
+        contexts
+            .iter()
+            .flat_map(|context| {
+                self.bindings
+                    .iter()
+                    .filter(move |binding| binding.context == *context)
+            })
+            .filter(|binding| {
+                self.lookup(contexts, &binding.keys) == Resolved::Action(binding.action)
+            })
+            .map(|binding| (binding.context, format_keys(&binding.keys), binding.action))
+            .collect()
+    }
+
+    fn lookup(&self, contexts: &[Context], keys: &[Chord]) -> Resolved {
+        for context in contexts {
+            let binding = self
+                .bindings
+                .iter()
+                .find(|binding| binding.context == *context && binding.keys.starts_with(keys));
+            if let Some(binding) = binding {
+                return match binding.keys.len() == keys.len() {
+                    true => Resolved::Action(binding.action),
+                    false => Resolved::Pending,
+                };
+            }
+        }
+        Resolved::Unbound
+    }
+}
+
+/// Bindings of the same stack whose keys are equal or start one another.
+fn conflicts(bindings: &[Binding]) -> BTreeSet<String> {
+    let mut conflicts = BTreeSet::new();
+    for stack in STACKS {
+        let stacked = bindings
+            .iter()
+            .filter(|binding| stack.contains(&binding.context))
+            .collect_vec();
+        for (a, b) in stacked.iter().tuple_combinations() {
+            if a.action != b.action && (a.keys.starts_with(&b.keys) || b.keys.starts_with(&a.keys))
+            {
+                conflicts.insert(format!(
+                    "{} is bound to {} {} and {} {}",
+                    format_keys(cmp::min_by_key(&a.keys, &b.keys, |keys| keys.len())),
+                    a.context,
+                    a.action,
+                    b.context,
+                    b.action
+                ));
+            }
+        }
+    }
+    conflicts
+}
+
+/// Shift is part of the character typed, and of backtab.
+fn normalize((modifiers, code): Chord) -> Chord {
+    match code {
+        KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => {
+            (modifiers - KeyModifiers::SHIFT, KeyCode::BackTab)
+        }
+        KeyCode::Char(_) | KeyCode::BackTab => (modifiers - KeyModifiers::SHIFT, code),
+        _ => (modifiers, code),
+    }
+}
+
+/// Chords separated by spaces, like "ctrl-x g".
+pub fn parse_keys(keys: &str) -> Result<Vec<Chord>, String> {
+    let chords: Vec<Chord> = keys.split_whitespace().map(parse_chord).try_collect()?;
+    if chords.is_empty() {
+        return Err(String::from("No key"));
+    }
+    Ok(chords)
+}
+
+fn parse_chord(chord: &str) -> Result<Chord, String> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut key = chord;
+    while let Some((modifier, rest)) = key.split_once('-') {
+        if rest.is_empty() {
+            break;
+        }
+        modifiers |= match modifier.to_lowercase().as_str() {
+            "ctrl" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return Err(format!("Unknown modifier {modifier} in {chord}")),
+        };
+        key = rest;
+    }
+
+    let code = match key.to_lowercase().as_str() {
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "enter" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "space" => KeyCode::Char(' '),
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        name => match (name.strip_prefix('f'), key.chars().exactly_one()) {
+            (_, Ok(c)) if modifiers.contains(KeyModifiers::SHIFT) => {
+                KeyCode::Char(c.to_ascii_uppercase())
+            }
+            (_, Ok(c)) => KeyCode::Char(c),
+            (Some(number), _) => number
+                .parse()
+                .map(KeyCode::F)
+                .map_err(|_| format!("Unknown key {key} in {chord}"))?,
+            _ => return Err(format!("Unknown key {key} in {chord}")),
+        },
+    };
+    Ok(normalize((modifiers, code)))
+}
+
+pub fn format_keys(keys: &[Chord]) -> String {
+    keys.iter().map(|chord| format_chord(*chord)).join(" ")
+}
+
+fn format_chord((modifiers, code): Chord) -> String {
+    let mut chord = String::new();
+    for (modifier, name) in [
+        (KeyModifiers::CONTROL, "ctrl-"),
+        (KeyModifiers::ALT, "alt-"),
+        (KeyModifiers::SHIFT, "shift-"),
+    ] {
+        if modifiers.contains(modifier) {
+            chord.push_str(name);
+        }
+    }
+    let key = match code {
+        KeyCode::Char(' ') => String::from("space"),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::F(number) => format!("f{number}"),
+        KeyCode::PageUp => String::from("pageup"),
+        KeyCode::PageDown => String::from("pagedown"),
+        KeyCode::BackTab => String::from("backtab"),
+        code => format!("{code:?}").to_lowercase(),
+    };
+    chord + &key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(modifiers: KeyModifiers, code: KeyCode) -> Chord {
+        (modifiers, code)
+    }
+
+    const FILES: &[Context] = &[Context::Files, Context::Playback, Context::Global];
+
+    #[test]
+    fn keys_are_parsed_and_formatted() {
+        for keys in [
+            "ctrl-t",
+            "g g",
+            "backtab",
+            "alt-pagedown",
+            "f5",
+            "space",
+            "-",
+            "G",
+        ] {
+            assert_eq!(format_keys(&parse_keys(keys).unwrap()), keys);
+        }
+        assert_eq!(parse_keys("shift-tab"), parse_keys("backtab"));
+        assert_eq!(
+            parse_keys("shift-g").unwrap(),
+            vec![key(KeyModifiers::NONE, KeyCode::Char('G'))]
+        );
+        assert!(parse_keys("hyper-x").is_err());
+        assert!(parse_keys("").is_err());
+    }
+
+    #[test]
+    fn defaults_follow_the_contexts() {
+        let mut keymap = Keymap::new(Preset::Default, &Bindings::new());
+        assert!(keymap.errors.is_empty(), "{:?}", keymap.errors);
+
+        let ctrl_t = key(KeyModifiers::CONTROL, KeyCode::Char('t'));
+        assert_eq!(
+            keymap.resolve(FILES, ctrl_t).1,
+            Resolved::Action(Action::SwitchPlayMode)
+        );
+        assert_eq!(
+            keymap
+                .resolve(&[Context::Directories, Context::Global], ctrl_t)
+                .1,
+            Resolved::Unbound
+        );
+        // Typed in the filter
+        assert_eq!(
+            keymap
+                .resolve(FILES, key(KeyModifiers::NONE, KeyCode::Char('k')))
+                .1,
+            Resolved::Unbound
+        );
+        assert_eq!(
+            keymap
+                .resolve(FILES, key(KeyModifiers::CONTROL, KeyCode::Char('c')))
+                .1,
+            Resolved::Action(Action::Quit)
+        );
+    }
+
+    #[test]
+    fn sequences_wait_for_their_last_chord() {
+        let bindings = Bindings::from([(
+            String::from("files"),
+            HashMap::from([(String::from("play_next"), vec![String::from("g n")])]),
+        )]);
+        let mut keymap = Keymap::new(Preset::Default, &bindings);
+        let g = key(KeyModifiers::SHIFT, KeyCode::Char('g'));
+        let n = key(KeyModifiers::NONE, KeyCode::Char('n'));
+
+        assert_eq!(keymap.resolve(FILES, g).1, Resolved::Pending);
+        assert_eq!(
+            keymap.resolve(FILES, n).1,
+            Resolved::Action(Action::PlayNext)
+        );
+        // Replaced by the sequence
+        assert_eq!(
+            keymap
+                .resolve(FILES, key(KeyModifiers::CONTROL, KeyCode::Char('n')))
+                .1,
+            Resolved::Unbound
+        );
+
+        assert_eq!(keymap.resolve(FILES, g).1, Resolved::Pending);
+        assert_eq!(
+            keymap
+                .resolve(FILES, key(KeyModifiers::CONTROL, KeyCode::Char('t')))
+                .1,
+            Resolved::Action(Action::SwitchPlayMode)
+        );
+    }
+
+    #[test]
+    fn failed_sequences_leave_their_chords_to_the_input() {
+        let bindings = Bindings::from([(
+            String::from("files"),
+            HashMap::from([(String::from("first_line"), vec![String::from("g g")])]),
+        )]);
+        let mut keymap = Keymap::new(Preset::Default, &bindings);
+        let g = key(KeyModifiers::NONE, KeyCode::Char('g'));
+        let x = key(KeyModifiers::NONE, KeyCode::Char('x'));
+        let ctrl_t = key(KeyModifiers::CONTROL, KeyCode::Char('t'));
+
+        assert_eq!(keymap.resolve(FILES, g), (vec![], Resolved::Pending));
+        assert_eq!(keymap.resolve(FILES, x), (vec![g], Resolved::Unbound));
+        assert_eq!(keymap.resolve(FILES, x), (vec![], Resolved::Unbound));
+
+        assert_eq!(keymap.resolve(FILES, g), (vec![], Resolved::Pending));
+        assert_eq!(
+            keymap.resolve(FILES, ctrl_t),
+            (vec![g], Resolved::Action(Action::SwitchPlayMode))
+        );
+        assert_eq!(keymap.resolve(FILES, g), (vec![], Resolved::Pending));
+        assert_eq!(
+            keymap.resolve(FILES, g),
+            (vec![], Resolved::Action(Action::FirstLine))
+        );
+    }
+
+    #[test]
+    fn conflicts_are_reported() {
+        let bindings = Bindings::from([
+            (
+                String::from("files"),
+                HashMap::from([
+                    (String::from("play_next"), vec![String::from("ctrl-t")]),
+                    (String::from("play_previous"), vec![String::from("x y")]),
+                    (String::from("jump"), vec![String::from("j")]),
+                    (
+                        String::from("select"),
+                        vec![String::from("x"), String::from("nope-x")],
+                    ),
+                ]),
+            ),
+            // Not looked up with Files
+            (
+                String::from("directories"),
+                HashMap::from([(String::from("delete"), vec![String::from("x")])]),
+            ),
+            (
+                String::from("playlist"),
+                HashMap::from([(String::from("delete"), vec![String::from("d")])]),
+            ),
+        ]);
+        let keymap = Keymap::new(Preset::Default, &bindings);
+        assert_eq!(
+            keymap.errors,
+            vec![
+                "Unknown action jump in files",
+                "Unknown context playlist",
+                "files select: Unknown modifier nope in nope-x",
+                "ctrl-t is bound to playback switch_play_mode and files play_next",
+                "x is bound to files select and files play_previous",
+            ]
+        );
+    }
+
+    #[test]
+    fn active_bindings_skip_hidden_ones() {
+        let bindings = Bindings::from([(
+            String::from("files"),
+            HashMap::from([(String::from("play_next"), vec![String::from("ctrl-t")])]),
+        )]);
+        let keymap = Keymap::new(Preset::Default, &bindings);
+        let active = keymap.active(FILES);
+
+        let keys_of = |action| {
+            active
+                .iter()
+                .filter(|(_, _, bound)| *bound == action)
+                .map(|(_, keys, _)| keys.as_str())
+                .collect_vec()
+        };
+        assert_eq!(keys_of(Action::PlayNext), vec!["ctrl-t"]);
+        // Looked up in Files first
+        assert!(keys_of(Action::SwitchPlayMode).is_empty());
+        assert_eq!(keys_of(Action::Quit), vec!["ctrl-c", "ctrl-d"]);
+        assert_eq!(keys_of(Action::Help), vec!["f1"]);
+        assert!(keys_of(Action::Delete).is_empty());
+        assert_eq!(active[0].0, Context::Files);
+    }
+
+    #[test]
+    fn vim_preset_binds_letters_in_normal_mode() {
+        let mut keymap = Keymap::new(Preset::Vim, &Bindings::new());
+        assert!(keymap.errors.is_empty(), "{:?}", keymap.errors);
+        let normal = &[
+            Context::Normal,
+            Context::Files,
+            Context::Playback,
+            Context::Global,
+        ];
+        let g = key(KeyModifiers::NONE, KeyCode::Char('g'));
+
+        assert_eq!(keymap.resolve(normal, g).1, Resolved::Pending);
+        assert_eq!(
+            keymap.resolve(normal, g).1,
+            Resolved::Action(Action::FirstLine)
+        );
+        assert_eq!(
+            keymap
+                .resolve(normal, key(KeyModifiers::SHIFT, KeyCode::Char('G')))
+                .1,
+            Resolved::Action(Action::LastLine)
+        );
+        assert_eq!(
+            keymap
+                .resolve(normal, key(KeyModifiers::CONTROL, KeyCode::Char('d')))
+                .1,
+            Resolved::Action(Action::PageDown)
+        );
+        assert_eq!(
+            keymap
+                .resolve(normal, key(KeyModifiers::NONE, KeyCode::Char('/')))
+                .1,
+            Resolved::Action(Action::StartFilter)
+        );
+
+        // Typed in the filter
+        let filter = &[Context::Filter, Context::Global];
+        assert_eq!(keymap.resolve(filter, g).1, Resolved::Unbound);
+        assert_eq!(
+            keymap
+                .resolve(filter, key(KeyModifiers::NONE, KeyCode::Esc))
+                .1,
+            Resolved::Action(Action::StopFilter)
+        );
+    }
+}
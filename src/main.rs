@@ -2,21 +2,38 @@
 mod app;
 #[cfg(feature = "mpv")]
 mod backend_mpv;
+mod backend_null;
 mod backend_rodio;
 pub mod backend_trait;
+mod backend_wav;
+mod bookmarks;
+mod cli;
+mod columns;
 mod constants;
 mod directories;
 mod echo_area;
 mod files;
+mod keymap;
+mod render;
+mod theme;
+mod time_stretch;
 mod ui;
 mod utils;
 use app::App;
+use cli::{Args, Command, ConfigCommand};
 use crossterm::{
-    event::{self, poll, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
+    event::{
+        self, poll, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers,
+        KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{
+        disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement, EnterAlternateScreen,
+        LeaveAlternateScreen,
+    },
 };
 use itertools::Itertools;
+use log::LevelFilter;
 use log4rs::config::{Appender, Root};
 use log4rs::Config;
 use log4rs::{append::file::FileAppender, encode::pattern::PatternEncoder};
@@ -26,23 +43,14 @@ use std::io::{self, stdout};
 use std::panic::set_hook;
 use std::panic::take_hook;
 use std::{backtrace::Backtrace, time::Duration};
-
-fn switch_play_mode() {
-    let mut config = utils::config::get_config();
-    // TODO : variant_count, enumerate PlayModes
-    let play_mode = match config.play_mode {
-        utils::config::PlayMode::Queue => utils::config::PlayMode::Random,
-        utils::config::PlayMode::Random => utils::config::PlayMode::Queue,
-    };
-
-    config.play_mode = play_mode;
-    utils::config::update_config(&config);
-}
+use utils::config::ConfigError;
+use utils::settings::Settings;
 
 pub fn restore_tui() -> io::Result<()> {
     disable_raw_mode()?;
     execute!(stdout(), LeaveAlternateScreen)?;
     execute!(stdout(), DisableMouseCapture)?;
+    execute!(stdout(), PopKeyboardEnhancementFlags)?;
 
     let trace = Backtrace::capture();
 
@@ -56,16 +64,31 @@ pub fn restore_tui() -> io::Result<()> {
     Ok(())
 }
 
-fn main_app() -> Result<(), Box<dyn std::error::Error>> {
-    utils::config::get_home_dir();
+/// `invalid` is why the config file was moved aside, shown until it is fixed.
+fn main_app(invalid: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    utils::config::get_home_dir()?;
 
     enable_raw_mode()?; // crossterm terminal setup
     let mut stdout = stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?; // crossterm event setup
+
+    // Without it ctrl-shift-p arrives as ctrl-p
+    if supports_keyboard_enhancement().unwrap_or(false) {
+        execute!(
+            stdout,
+            PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+        )?;
+    }
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
     terminal.hide_cursor()?;
-    let mut app = App::new();
+    let mut app = App::new(Settings::load());
+    if let Some(invalid) = invalid {
+        app.config_error = Some(match app.config_error.take() {
+            Some(errors) => format!("{invalid} {errors}"),
+            None => invalid,
+        });
+    }
 
     let original_hook = take_hook();
     set_hook(Box::new(move |panic_info| {
@@ -85,20 +108,20 @@ fn main_app() -> Result<(), Box<dyn std::error::Error>> {
                     // Skip events that are not KeyEventKind::Press
                     continue;
                 }
+            }
 
-                let frame = terminal.get_frame();
-                match app.handle_event(&frame, &event) {
-                    Ok(_) => {
-                        terminal.draw(|f| ui::ui(f, &mut app))?;
-                    }
-                    Err(_) => {
-                        break;
-                        // Manage handling err
-                    }
+            let frame = terminal.get_frame();
+            match app.handle_event(&frame, &event) {
+                Ok(_) => {
+                    terminal.draw(|f| ui::ui(f, &mut app))?;
+                }
+                Err(_) => {
+                    break;
+                    // Manage handling err
                 }
-
-                log::debug!("Event done");
             }
+
+            log::debug!("Event done");
         } else {
             app.handle_tick();
             terminal.draw(|f| ui::ui(f, &mut app))?;
@@ -109,7 +132,8 @@ fn main_app() -> Result<(), Box<dyn std::error::Error>> {
     execute!(
         terminal.backend_mut(),
         LeaveAlternateScreen,
-        DisableMouseCapture
+        DisableMouseCapture,
+        PopKeyboardEnhancementFlags
     )?;
     terminal.clear()?;
     crossterm::terminal::disable_raw_mode()?;
@@ -118,25 +142,60 @@ fn main_app() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn log_setup() {
+fn log_setup(log_level: LevelFilter) {
     let logfile = FileAppender::builder()
         .encoder(Box::<PatternEncoder>::default())
         .build("log/dirplayer.log")
         .unwrap();
 
-    let config = utils::config::get_set_config();
-
     let config = Config::builder()
         .appender(Appender::builder().build("logfile", Box::new(logfile)))
-        .build(Root::builder().appender("logfile").build(config.log_level))
+        .build(Root::builder().appender("logfile").build(log_level))
         .unwrap();
 
     log4rs::init_config(config).unwrap();
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    log_setup();
-    match main_app() {
+    let args: Args = argh::from_env();
+    let (config, invalid) = match utils::config::get_set_config() {
+        Ok(config) => (config, None),
+        // Moved aside, defaults were written instead
+        Err(e @ ConfigError::Invalid { .. }) => (utils::config::get_config()?, Some(e)),
+        Err(e) => return Err(e.into()),
+    };
+    log_setup(config.log_level);
+    let invalid = invalid.map(|e| {
+        eprintln!("{e}");
+        log::warn!("{e}");
+        e.to_string()
+    });
+    if let Some(device) = args.device {
+        utils::config::update_config(&utils::config::Config {
+            output_device: Some(device),
+            ..utils::config::get_config()?
+        })?;
+    }
+    match args.command {
+        Some(Command::Render(render_args)) => return render::render(&render_args),
+        Some(Command::Devices(_)) => {
+            let config = utils::config::get_config()?;
+            for device in backend_rodio::output_devices() {
+                let current = config.output_device.as_ref() == Some(&device);
+                println!("{} {}", if current { "*" } else { " " }, device);
+            }
+            return Ok(());
+        }
+        Some(Command::Config(config_args)) => match config_args.command {
+            ConfigCommand::Migrate(migrate_args) => {
+                let path = utils::config::migrate_config(migrate_args.to)?;
+                println!("Config saved to {}", path.display());
+                return Ok(());
+            }
+        },
+        None => {}
+    }
+    match main_app(invalid) {
         Ok(_) => {}
         Err(_err) => {
             let stdout = stdout();
@@ -155,17 +214,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 
-    // TODO: show next
     // TODO: remove epiubs from files
     // TODO: unarchive and read
     // TODO: read mpc
     // TODO: more info in echo area. Maybe refresh not on tick but on event
     // TODO: fix filtering of songs (should be no rar, etc.)
     // TODO: display filterg
-    // TODO: display help
     // TODO: movement to echo area
     // TODO: home / end movements
     // TODO: better event matrix
-    // TODO: better shortcut management
-    // TODO: volume control?
 }
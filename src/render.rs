@@ -0,0 +1,43 @@
+use crate::backend_wav::Wav;
+use crate::cli::RenderArgs;
+use crate::files::Files;
+use crate::utils;
+use crate::utils::config::Backend;
+use crate::utils::settings::Settings;
+
+/// Play the filtered files into a wav file, in the order the player would.
+pub fn render(args: &RenderArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = utils::config::get_config()?;
+    // Do not open an audio device
+    config.backend = Backend::Null;
+    let mut state = utils::state::get_state();
+    if let Some(mode) = args.mode {
+        state.play_mode = mode;
+    }
+
+    let wav = Wav::new(&args.output);
+    let mut files = Files::new(Settings::in_memory(config, state));
+    files.set_backend(wav.clone());
+    files.update_lines();
+    files.filter = args.filter.clone();
+    files.current_file = String::default();
+    files.update_lines_filtered();
+
+    let count = args.count.unwrap_or(files.lines_filtered.len());
+    if count > 0 {
+        files.play_file();
+    }
+    let mut started = 0;
+    // Stops at the end of the queue, when no song is started anymore
+    while wav.started() > started {
+        started = wav.started();
+        println!("{}/{} {}", started, count, files.current_file);
+        if started >= count {
+            break;
+        }
+        files.autoplay();
+    }
+
+    wav.finalize()?;
+    Ok(())
+}
@@ -0,0 +1,391 @@
+use ratatui::style::{palette::tailwind, Color, Modifier, Style};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use strum::{Display, EnumIter, EnumString};
+
+// Styles of the interface by element. Built-in themes are written here, user
+// themes of the config change some elements of one of them. With NO_COLOR,
+// elements are told apart by modifiers only.
+
+#[derive(
+    Serialize, Deserialize, Display, EnumIter, EnumString, Clone, Copy, PartialEq, Eq, Hash, Debug,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Element {
+    /// Backgrounds of the tab titles
+    TabDirectories,
+    TabFiles,
+    TabBookmarks,
+    /// Background of the current tab title
+    TabSelected,
+    /// Background of the selected line
+    Selection,
+    /// Characters matching the filter
+    Match,
+    /// Typed text, directories and keys
+    Accent,
+    /// Borders and secondary text
+    Muted,
+    Error,
+    Playing,
+    Paused,
+    /// Borders of the help and the palette
+    Popup,
+    /// Border of the password prompt
+    Prompt,
+    Echo,
+}
+
+impl Element {
+    /// Colors of these elements are backgrounds, others are foregrounds.
+    fn is_background(self) -> bool {
+        matches!(
+            self,
+            Element::TabDirectories
+                | Element::TabFiles
+                | Element::TabBookmarks
+                | Element::TabSelected
+                | Element::Selection
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Display, EnumString, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Builtin {
+    #[default]
+    Dark,
+    Light,
+    HighContrast,
+}
+
+/// Colors of the file dates, from the start to the end of their year.
+#[derive(Serialize, Deserialize, EnumString, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum DateGradient {
+    Rainbow,
+    Sinebow,
+    Turbo,
+    Viridis,
+    Cividis,
+    Plasma,
+    Warm,
+    Cool,
+    /// Dates are muted
+    None,
+}
+
+impl DateGradient {
+    pub fn gradient(self) -> Option<colorous::Gradient> {
+        match self {
+            DateGradient::Rainbow => Some(colorous::RAINBOW),
+            DateGradient::Sinebow => Some(colorous::SINEBOW),
+            DateGradient::Turbo => Some(colorous::TURBO),
+            DateGradient::Viridis => Some(colorous::VIRIDIS),
+            DateGradient::Cividis => Some(colorous::CIVIDIS),
+            DateGradient::Plasma => Some(colorous::PLASMA),
+            DateGradient::Warm => Some(colorous::WARM),
+            DateGradient::Cool => Some(colorous::COOL),
+            DateGradient::None => None,
+        }
+    }
+}
+
+/// A user theme, as written in the config. Unknown names are reported by the theme.
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct ThemeConfig {
+    /// Built-in theme changed, the dark one when there is none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base: Option<String>,
+    /// Colors by element, like "yellow", "#ffcc00" or "238"
+    pub colors: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_gradient: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Theme {
+    pub tab_directories: Style,
+    pub tab_files: Style,
+    pub tab_bookmarks: Style,
+    pub tab_selected: Style,
+    pub selection: Style,
+    pub matched: Style,
+    pub accent: Style,
+    pub muted: Style,
+    pub error: Style,
+    pub playing: Style,
+    pub paused: Style,
+    pub popup: Style,
+    pub prompt: Style,
+    pub echo: Style,
+    pub dates: DateGradient,
+    /// Unknown themes, elements, colors and gradients
+    pub errors: Vec<String>,
+}
+
+/// Whether NO_COLOR is set, see https://no-color.org.
+pub fn no_color() -> bool {
+    env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+impl Theme {
+    /// The user theme or built-in theme called `name`, the dark one when there is none.
+    pub fn new(name: &str, themes: &HashMap<String, ThemeConfig>, no_color: bool) -> Theme {
+        if no_color {
+            return Theme::monochrome();
+        }
+        if let Some(config) = themes.get(name) {
+            return Theme::from_config(config);
+        }
+        match Builtin::from_str(name) {
+            Ok(builtin) => Theme::builtin(builtin),
+            Err(_) => Theme {
+                errors: vec![format!("Unknown theme {name}")],
+                ..Theme::builtin(Builtin::Dark)
+            },
+        }
+    }
+
+    fn from_config(config: &ThemeConfig) -> Theme {
+        let mut errors = vec![];
+        let base = match &config.base {
+            Some(name) => Builtin::from_str(name).unwrap_or_else(|_| {
+                errors.push(format!("Unknown base theme {name}"));
+                Builtin::Dark
+            }),
+            None => Builtin::Dark,
+        };
+        let mut theme = Theme {
+            errors,
+            ..Theme::builtin(base)
+        };
+        // Sorted for errors to come in the same order
+        let mut colors = config.colors.iter().collect::<Vec<_>>();
+        colors.sort();
+        for (name, color) in colors {
+            let Ok(element) = Element::from_str(name) else {
+                theme.errors.push(format!("Unknown element {name}"));
+                continue;
+            };
+            match Color::from_str(color) {
+                Ok(color) => {
+                    let style = theme.style_mut(element);
+                    *style = match element.is_background() {
+                        true => style.bg(color),
+                        false => style.fg(color),
+                    };
+                }
+                Err(_) => theme
+                    .errors
+                    .push(format!("Unknown color {color} for {element}")),
+            }
+        }
+        if let Some(name) = &config.date_gradient {
+            match DateGradient::from_str(name) {
+                Ok(dates) => theme.dates = dates,
+                Err(_) => theme.errors.push(format!("Unknown date gradient {name}")),
+            }
+        }
+        theme
+    }
+
+    pub fn builtin(builtin: Builtin) -> Theme {
+        match builtin {
+            Builtin::Dark => Theme {
+                tab_directories: tab(tailwind::SLATE.c200, tailwind::BLUE.c900),
+                tab_files: tab(tailwind::SLATE.c200, tailwind::EMERALD.c900),
+                tab_bookmarks: tab(tailwind::SLATE.c200, tailwind::AMBER.c900),
+                tab_selected: Style::default().bg(tailwind::RED.c700),
+                selection: Style::default().bg(Color::Rgb(51, 51, 51)),
+                matched: Style::default()
+                    .fg(Color::Green)
+                    .add_modifier(Modifier::BOLD),
+                accent: Style::default().fg(Color::Rgb(255, 255, 0)),
+                muted: Style::default().fg(Color::DarkGray),
+                error: Style::default().fg(Color::Red),
+                playing: Style::default().fg(Color::Green),
+                paused: Style::default().fg(Color::Yellow),
+                popup: Style::default().fg(tailwind::SLATE.c500),
+                prompt: Style::default().fg(tailwind::RED.c700),
+                echo: Style::default().fg(Color::Rgb(255, 255, 0)),
+                dates: DateGradient::Rainbow,
+                errors: vec![],
+            },
+            Builtin::Light => Theme {
+                tab_directories: tab(tailwind::SLATE.c900, tailwind::BLUE.c200),
+                tab_files: tab(tailwind::SLATE.c900, tailwind::EMERALD.c200),
+                tab_bookmarks: tab(tailwind::SLATE.c900, tailwind::AMBER.c200),
+                tab_selected: Style::default().bg(tailwind::RED.c300),
+                selection: Style::default().bg(tailwind::SLATE.c200),
+                matched: Style::default()
+                    .fg(tailwind::GREEN.c700)
+                    .add_modifier(Modifier::BOLD),
+                accent: Style::default().fg(tailwind::AMBER.c700),
+                muted: Style::default().fg(tailwind::SLATE.c400),
+                error: Style::default().fg(tailwind::RED.c600),
+                playing: Style::default().fg(tailwind::GREEN.c700),
+                paused: Style::default().fg(tailwind::AMBER.c700),
+                popup: Style::default().fg(tailwind::SLATE.c400),
+                prompt: Style::default().fg(tailwind::RED.c500),
+                echo: Style::default().fg(tailwind::AMBER.c700),
+                dates: DateGradient::Viridis,
+                errors: vec![],
+            },
+            Builtin::HighContrast => Theme {
+                tab_directories: tab(Color::Black, Color::White),
+                tab_files: tab(Color::Black, Color::White),
+                tab_bookmarks: tab(Color::Black, Color::White),
+                tab_selected: Style::default().fg(Color::Black).bg(Color::LightYellow),
+                selection: Style::default().fg(Color::White).bg(Color::Blue),
+                matched: Style::default()
+                    .fg(Color::LightCyan)
+                    .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+                accent: Style::default().fg(Color::LightYellow),
+                muted: Style::default().fg(Color::White),
+                error: Style::default()
+                    .fg(Color::LightRed)
+                    .add_modifier(Modifier::BOLD),
+                playing: Style::default().fg(Color::LightGreen),
+                paused: Style::default().fg(Color::LightYellow),
+                popup: Style::default().fg(Color::White),
+                prompt: Style::default().fg(Color::LightRed),
+                echo: Style::default().fg(Color::LightYellow),
+                dates: DateGradient::None,
+                errors: vec![],
+            },
+        }
+    }
+
+    /// No color at all, the selections are reversed.
+    pub fn monochrome() -> Theme {
+        let plain = Style::default();
+        Theme {
+            tab_directories: plain,
+            tab_files: plain,
+            tab_bookmarks: plain,
+            tab_selected: plain.add_modifier(Modifier::REVERSED),
+            selection: plain.add_modifier(Modifier::REVERSED),
+            matched: plain.add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+            accent: plain,
+            muted: plain,
+            error: plain.add_modifier(Modifier::BOLD),
+            playing: plain.add_modifier(Modifier::BOLD),
+            paused: plain,
+            popup: plain,
+            prompt: plain,
+            echo: plain,
+            dates: DateGradient::None,
+            errors: vec![],
+        }
+    }
+
+    fn style_mut(&mut self, element: Element) -> &mut Style {
+        match element {
+            Element::TabDirectories => &mut self.tab_directories,
+            Element::TabFiles => &mut self.tab_files,
+            Element::TabBookmarks => &mut self.tab_bookmarks,
+            Element::TabSelected => &mut self.tab_selected,
+            Element::Selection => &mut self.selection,
+            Element::Match => &mut self.matched,
+            Element::Accent => &mut self.accent,
+            Element::Muted => &mut self.muted,
+            Element::Error => &mut self.error,
+            Element::Playing => &mut self.playing,
+            Element::Paused => &mut self.paused,
+            Element::Popup => &mut self.popup,
+            Element::Prompt => &mut self.prompt,
+            Element::Echo => &mut self.echo,
+        }
+    }
+}
+
+fn tab(fg: Color, bg: Color) -> Style {
+    Style::default().fg(fg).bg(bg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_themes_change_a_builtin_theme() {
+        let config = ThemeConfig {
+            base: Some(String::from("light")),
+            colors: HashMap::from([
+                (String::from("selection"), String::from("#102030")),
+                (String::from("match"), String::from("magenta")),
+                (String::from("error"), String::from("nope")),
+                (String::from("border"), String::from("red")),
+            ]),
+            date_gradient: Some(String::from("none")),
+        };
+        let themes = HashMap::from([(String::from("mine"), config)]);
+
+        let theme = Theme::new("mine", &themes, false);
+        let light = Theme::builtin(Builtin::Light);
+        assert_eq!(theme.selection, light.selection.bg(Color::Rgb(16, 32, 48)));
+        // Modifiers are kept
+        assert_eq!(theme.matched, light.matched.fg(Color::Magenta));
+        assert_eq!(theme.error, light.error);
+        assert_eq!(theme.dates, DateGradient::None);
+        assert_eq!(
+            theme.errors,
+            vec!["Unknown element border", "Unknown color nope for error"]
+        );
+
+        let config = ThemeConfig {
+            base: Some(String::from("solarized")),
+            colors: HashMap::new(),
+            date_gradient: Some(String::from("magma")),
+        };
+        let themes = HashMap::from([(String::from("mine"), config)]);
+        let theme = Theme::new("mine", &themes, false);
+        assert_eq!(theme.dates, DateGradient::Rainbow);
+        assert_eq!(
+            theme.errors,
+            vec![
+                "Unknown base theme solarized",
+                "Unknown date gradient magma"
+            ]
+        );
+
+        assert_eq!(
+            Theme::new("high_contrast", &themes, false),
+            Theme::builtin(Builtin::HighContrast)
+        );
+        assert_eq!(
+            Theme::new("neon", &themes, false).errors,
+            vec!["Unknown theme neon"]
+        );
+        assert_eq!(Theme::new("mine", &themes, true), Theme::monochrome());
+    }
+
+    #[test]
+    fn themes_are_read_from_toml() {
+        let config: ThemeConfig = toml::from_str(
+            r##"
+            base = "high_contrast"
+            date_gradient = "turbo"
+            [colors]
+            tab_selected = "#ff0000"
+            "##,
+        )
+        .unwrap();
+        let theme = Theme::from_config(&config);
+        assert_eq!(theme.errors, Vec::<String>::new());
+        assert_eq!(theme.dates, DateGradient::Turbo);
+        assert_eq!(
+            theme.tab_selected,
+            Theme::builtin(Builtin::HighContrast)
+                .tab_selected
+                .bg(Color::Rgb(255, 0, 0))
+        );
+    }
+}
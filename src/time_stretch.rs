@@ -0,0 +1,186 @@
+use rodio::Source;
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Changes the speed of a source without changing its pitch, by overlap-add:
+// windows of the source are read HOP * speed frames apart, and written HOP
+// frames apart. Hann windows overlapping by half sum to one, so that the
+// source is left untouched at speed 1.
+
+/// Frames per window.
+const WINDOW: usize = 2048;
+const HOP: usize = WINDOW / 2;
+
+/// Speed shared with the player, as f32 bits.
+#[derive(Clone)]
+pub struct Speed(Arc<AtomicU32>);
+
+impl Speed {
+    pub fn new(speed: f32) -> Self {
+        Self(Arc::new(AtomicU32::new(speed.to_bits())))
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, speed: f32) {
+        self.0.store(speed.to_bits(), Ordering::Relaxed);
+    }
+}
+
+pub struct TimeStretch<S> {
+    source: S,
+    speed: Speed,
+    channels: usize,
+    window: Vec<f32>,
+    // Interleaved samples, from the start of the next window
+    input: VecDeque<f32>,
+    // Sum of the windows written so far, WINDOW frames long
+    overlap: Vec<f32>,
+    output: VecDeque<f32>,
+    // Output samples to drop, see `new`
+    skip: usize,
+    // Source samples to drop, when reading windows further apart than their length
+    discard: usize,
+    source_ended: bool,
+}
+
+impl<S> TimeStretch<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(source: S, speed: Speed) -> Self {
+        let channels = source.channels() as usize;
+        let window = (0..WINDOW)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / WINDOW as f32).cos())
+            .collect();
+        Self {
+            source,
+            speed,
+            channels,
+            window,
+            // The first window only fades in, start it half a window early and drop that half
+            input: VecDeque::from(vec![0.0; HOP * channels]),
+            overlap: vec![0.0; WINDOW * channels],
+            output: VecDeque::new(),
+            skip: HOP * channels,
+            discard: 0,
+            source_ended: false,
+        }
+    }
+
+    fn process(&mut self) {
+        while self.discard > 0 && self.source.next().is_some() {
+            self.discard -= 1;
+        }
+        while self.input.len() < WINDOW * self.channels && !self.source_ended {
+            match self.source.next() {
+                Some(sample) => self.input.push_back(sample),
+                None => self.source_ended = true,
+            }
+        }
+
+        if self.input.is_empty() {
+            self.output.extend(self.overlap.drain(..));
+            return;
+        }
+
+        for (frame, weight) in self.window.iter().enumerate() {
+            for channel in 0..self.channels {
+                let index = frame * self.channels + channel;
+                let sample = self.input.get(index).copied().unwrap_or(0.0);
+                self.overlap[index] += sample * weight;
+            }
+        }
+
+        let ready = HOP * self.channels;
+        self.output.extend(self.overlap.drain(..ready));
+        self.overlap.extend(std::iter::repeat_n(0.0, ready));
+
+        let hop = (HOP as f32 * self.speed.get()).round() as usize * self.channels;
+        self.discard = hop.saturating_sub(self.input.len());
+        self.input.drain(..hop.min(self.input.len()));
+
+        let skipped = self.skip.min(self.output.len());
+        self.output.drain(..skipped);
+        self.skip -= skipped;
+    }
+}
+
+impl<S> Iterator for TimeStretch<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        while self.output.is_empty() {
+            if self.input.is_empty() && self.overlap.is_empty() {
+                return None;
+            }
+            self.process();
+        }
+        self.output.pop_front()
+    }
+}
+
+impl<S> Source for TimeStretch<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn song(frames: usize) -> SamplesBuffer<f32> {
+        let samples = (0..frames * 2)
+            .map(|i| ((i / 2) as f32 / 20.0).sin() * 0.5)
+            .collect::<Vec<f32>>();
+        SamplesBuffer::new(2, 44100, samples)
+    }
+
+    #[test]
+    fn normal_speed_keeps_source() {
+        let source = song(10 * WINDOW).collect::<Vec<f32>>();
+        let stretched = TimeStretch::new(song(10 * WINDOW), Speed::new(1.0)).collect::<Vec<f32>>();
+
+        assert!(stretched.len() >= source.len());
+        for (a, b) in source.iter().zip(stretched.iter()) {
+            assert!((a - b).abs() < 1e-4, "{a} != {b}");
+        }
+        assert!(stretched[source.len()..].iter().all(|s| s.abs() < 1e-4));
+    }
+
+    #[test]
+    fn speed_changes_length() {
+        let frames = 100 * WINDOW;
+        for speed in [0.5, 1.5, 3.0] {
+            let stretched = TimeStretch::new(song(frames), Speed::new(speed)).count() / 2;
+            let expected = frames as f32 / speed;
+            let error = (stretched as f32 - expected).abs() / expected;
+            assert!(error < 0.05, "speed {speed}: {stretched} frames");
+        }
+    }
+}
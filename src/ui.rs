@@ -1,8 +1,14 @@
-use crate::app::{App, Tab};
+use crate::app::{App, Help, Palette, PasswordPrompt, Tab};
+use crate::columns::{ColumnKind, Columns};
+use crate::constants::SongState;
 use crate::directories::State;
 use crate::files::{FileLine, Files};
+use crate::keymap::{Mode, Preset};
+use crate::theme::Theme;
 use crate::utils;
-use chrono::{DateTime, Utc};
+use crate::utils::archive;
+use crate::utils::config::PlayMode;
+use crate::utils::config::Status;
 // use crossterm::style::Stylize;
 use itertools::Itertools;
 use ratatui::widgets::Cell;
@@ -13,10 +19,13 @@ use ratatui::{
     style::{palette::tailwind, Color, Style, Stylize},
     symbols,
     text::{Line, Span},
-    widgets::{Block, Borders, Padding, Paragraph, Tabs, Widget},
+    widgets::{Block, Borders, Clear, LineGauge, Padding, Paragraph, Tabs, Widget},
     Frame,
 };
+use std::cmp;
+use std::path::Path;
 use std::rc::Rc;
+use std::time::Duration;
 use strum::{Display, EnumIter, FromRepr, IntoEnumIterator};
 
 #[derive(Default, Clone, Copy, Display, FromRepr, EnumIter, Debug)]
@@ -32,15 +41,23 @@ pub enum MenuTab {
     Directories,
     #[strum(to_string = "Files")]
     Files,
+    #[strum(to_string = "Bookmarks")]
+    Bookmarks,
 }
 
 impl MenuTab {
-    /// Return tab's name as a styled `Line`
-    fn title(self) -> Line<'static> {
+    fn label(self) -> String {
         format!("  {self}  ")
-            .fg(tailwind::SLATE.c200)
-            .bg(self.palette().c900)
-            .into()
+    }
+
+    /// Return tab's name as a styled `Line`
+    fn title(self, theme: &Theme) -> Line<'static> {
+        let style = match self {
+            Self::Directories => theme.tab_directories,
+            Self::Files => theme.tab_files,
+            Self::Bookmarks => theme.tab_bookmarks,
+        };
+        Line::from(self.label()).style(style)
     }
 
     /// A block surrounding the tab's content
@@ -56,6 +73,7 @@ impl MenuTab {
         match self {
             Self::Directories => tailwind::BLUE,
             Self::Files => tailwind::EMERALD,
+            Self::Bookmarks => tailwind::AMBER,
         }
     }
 }
@@ -73,42 +91,124 @@ impl Widget for MenuTab {
                     .block(self.block())
                     .render(area, buf);
             }
+            Self::Bookmarks => {
+                Paragraph::new("Bookmarks")
+                    .block(self.block())
+                    .render(area, buf);
+            }
         }
     }
 }
 
+/// Narrower terminals hide the dates of the files.
+const NARROW: u16 = 60;
+/// Wider terminals show the files played next beside the list.
+const WIDE: u16 = 120;
+const SIDE_WIDTH: u16 = 40;
+
+/// Tab bar, list, footer, now playing, and side panel, empty unless the terminal is wide.
 pub fn get_chunks(f: &Frame) -> Rc<[Rect]> {
-    Layout::default()
+    layout(f.size())
+}
+
+fn layout(area: Rect) -> Rc<[Rect]> {
+    let rows = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
             Constraint::Length(1),
             Constraint::Fill(1),
             Constraint::Length(3),
+            // Now playing
+            Constraint::Length(4),
         ])
-        .split(f.size())
+        .split(area);
+    let body = rows[1];
+    let (list, side) = match body.width >= WIDE {
+        true => {
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Fill(1), Constraint::Length(SIDE_WIDTH)])
+                .split(body);
+            (columns[0], columns[1])
+        }
+        false => (body, Rect::new(body.right(), body.y, 0, body.height)),
+    };
+    Rc::from([rows[0], list, rows[2], rows[3], side])
+}
+
+const TABS: [Tab; 3] = [Tab::Directories, Tab::Files, Tab::Bookmarks];
+
+/// Tab whose title is at `column` of the tab bar.
+pub fn tab_at(area: Rect, column: u16) -> Option<Tab> {
+    let mut left = area.x;
+    for (menu_tab, tab) in MenuTab::iter().zip(TABS) {
+        let right = left + Line::from(menu_tab.label()).width() as u16;
+        if (left..right).contains(&column) {
+            return Some(tab);
+        }
+        // Divider
+        left = right + 1;
+    }
+    None
+}
+
+/// Title and progress rows of the now playing area.
+pub fn now_playing_rows(area: Rect) -> Rc<[Rect]> {
+    Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(1), Constraint::Length(1)])
+        .split(Block::default().borders(Borders::ALL).inner(area))
+}
+
+/// Position, and duration when known, as drawn before the progress bar.
+pub fn progress_label(position: Duration, duration: Option<Duration>) -> String {
+    match duration {
+        Some(duration) => format!(
+            "{} / {}",
+            utils::format_duration(position),
+            utils::format_duration(duration)
+        ),
+        None => utils::format_duration(position),
+    }
+}
+
+/// Part of the song at `column` of the progress bar drawn after `label`.
+pub fn seek_ratio(row: Rect, label: &str, column: u16) -> Option<f64> {
+    // The bar starts after the label and a space
+    let start = row.x + Line::from(label).width() as u16 + 1;
+    if column < start || column >= row.right() {
+        return None;
+    }
+    Some(f64::from(column - start) / f64::from(row.right() - start))
 }
 
 pub fn ui(f: &mut Frame, app: &mut App) {
     let chunks = crate::ui::get_chunks(f);
 
-    let titles = MenuTab::iter().map(MenuTab::title);
-    let highlight_style = (Color::default(), tailwind::RED.c700);
-    let selected_tab_index = match app.current_place {
-        Tab::Directories => 0,
-        Tab::Files => 1,
-    };
+    let theme = &app.theme;
+    let titles = MenuTab::iter().map(|menu_tab| menu_tab.title(theme));
+    let selected_tab_index = TABS.iter().position(|tab| *tab == app.current_place);
 
     let tabs_widget = Tabs::new(titles)
-        .highlight_style(highlight_style)
-        .select(selected_tab_index)
+        .highlight_style(theme.tab_selected)
+        .select(selected_tab_index.unwrap_or_default())
         .padding("", "")
         .divider(" ");
 
     f.render_widget(tabs_widget, chunks[0]);
 
+    if let Some(config_error) = &app.config_error {
+        let config_error = Paragraph::new(
+            Line::from(config_error.clone())
+                .style(theme.error)
+                .right_aligned(),
+        );
+        f.render_widget(config_error, chunks[0]);
+    }
+
     match app.current_place {
         Tab::Directories => {
-            let completions_spans = app.directories.get_displayable_completions();
+            let completions_spans = app.directories.get_displayable_completions(theme.matched);
 
             let mut directories = app
                 .directories
@@ -116,12 +216,15 @@ pub fn ui(f: &mut Frame, app: &mut App) {
                 .iter()
                 .enumerate()
                 .map(|(i, working_path)| {
-                    let mut style = Style::default().fg(Color::Yellow);
+                    let mut style = match working_path.status {
+                        Status::Inactive => theme.muted,
+                        _ => theme.accent,
+                    };
 
                     if i == (app.directories.line_index as usize)
                         && app.directories.state != State::Editing
                     {
-                        style = style.bg(Color::Rgb(51, 51, 51));
+                        style = style.patch(theme.selection);
                     };
 
                     let mut line = Line::from(Span::styled(working_path.path.clone(), style));
@@ -145,15 +248,12 @@ pub fn ui(f: &mut Frame, app: &mut App) {
                 directories.push(Line::from(candidate_line));
             };
 
-            // let border_color_middle = Color::White;
-            let border_color_middle = Color::DarkGray;
-
             // let constraints = &[Constraint::Percentage(80), Constraint::Length(30)];
             let constraints = &[];
             let list = Paragraph::new(directories).block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .border_style(Style::default().fg(border_color_middle)),
+                    .border_style(theme.muted),
             );
 
             let mut state = TableState::default();
@@ -167,6 +267,19 @@ pub fn ui(f: &mut Frame, app: &mut App) {
         }
 
         Tab::Files => {
+            let (slice_free_index_high_border, slice_index, slice_free_index_low_border) =
+                app.files.visible_lines(chunks[1].height);
+
+            // Narrower terminals only show the first column
+            let columns = match chunks[1].width >= NARROW {
+                true => &app.columns.columns[..],
+                false => &app.columns.columns[..1],
+            };
+            if columns.iter().any(|column| column.kind.is_tag()) {
+                app.files
+                    .read_tags_of(slice_free_index_high_border..slice_free_index_low_border);
+            }
+
             // Display files
             let lines = &app.files.lines_filtered;
 
@@ -180,57 +293,381 @@ pub fn ui(f: &mut Frame, app: &mut App) {
                 log::debug!("LIST lines {:?}", dir_entries);
             }
 
-            let (slice_free_index_high_border, slice_index, slice_free_index_low_border) =
-                utils::get_borders(
-                    lines.len() as isize,
-                    chunks[1].height as isize + 1,
-                    app.files.line_index as isize,
-                );
-
-            let list_items =
-                &lines[slice_free_index_high_border as usize..slice_free_index_low_border as usize];
+            let list_items = &lines[slice_free_index_high_border..slice_free_index_low_border];
 
             let list_items: Vec<Row> = list_items
                 .iter()
-                .filter_map(|e| {
+                .map(|e| {
                     let FileLine { dir_entry, indices } = e;
-                    let metadata = dir_entry.metadata();
-                    if metadata.is_err() {
-                        return None;
-                    }
+                    let date_style = |date| match theme.dates.gradient() {
+                        Some(gradient) => {
+                            let (r, g, b) = Files::date_to_color(date, gradient);
+                            Style::default().fg(Color::Rgb(r, g, b))
+                        }
+                        None => theme.muted,
+                    };
+                    let cells = columns.iter().map(|column| match column.kind {
+                        ColumnKind::Filename => {
+                            let path = dir_entry.file_name().to_string_lossy();
+                            Cell::from(utils::style::string_to_styled_text(
+                                &path,
+                                indices,
+                                theme.matched,
+                            ))
+                        }
+                        kind => {
+                            let text = app.columns.text(
+                                kind,
+                                dir_entry,
+                                app.files.cached_tags(dir_entry).as_ref(),
+                                app.files.play_count(dir_entry),
+                            );
+                            let cell = Cell::from(text);
+                            match kind {
+                                ColumnKind::Created => cell.style(date_style(dir_entry.created)),
+                                ColumnKind::Modified => cell.style(date_style(dir_entry.modified)),
+                                _ => cell,
+                            }
+                        }
+                    });
+                    Row::new(cells)
+                })
+                .collect();
 
-                    let created = dir_entry.metadata().unwrap().created().unwrap();
-                    let date_time = DateTime::<Utc>::from(created);
-                    let dir_entry_path = dir_entry.file_name();
+            let constraints = Columns::constraints(columns);
+            let displayables = Table::new(list_items, constraints).highlight_style(theme.selection);
 
-                    let path: &str = dir_entry_path.to_str().unwrap();
+            let mut state = TableState::default();
+            state.select(Some(slice_index));
+            f.render_stateful_widget(displayables, chunks[1], &mut state);
 
-                    let spans = utils::style::string_to_styled_text(path, indices);
-                    let (r, g, b) = Files::date_to_color(created);
+            let archive_status = app.files.archive_status.read().unwrap();
+            let status_style = if archive_status.failed.is_empty() {
+                theme.muted
+            } else {
+                theme.error
+            };
+            let mut footer_block = Block::default().borders(Borders::ALL).title(
+                Line::from(archive_status.to_string())
+                    .style(status_style)
+                    .right_aligned(),
+            );
+            if app.keymap.preset == Preset::Vim {
+                let mode = match app.files_mode {
+                    Mode::Normal => "NORMAL",
+                    Mode::Filter => "FILTER",
+                };
+                footer_block = footer_block.title(Line::from(mode).style(theme.muted.bold()));
+            }
+            let mode_footer =
+                Paragraph::new(Line::from(app.files.filter.clone()).style(theme.accent))
+                    .block(footer_block);
+            f.render_widget(mode_footer, chunks[2]);
+        }
 
-                    let data = vec![
-                        Cell::from(spans),
-                        Cell::from(date_time.format("%Y-%m-%d %H-%M-%S").to_string())
-                            .style(Style::default().fg(ratatui::style::Color::Rgb(r, g, b))),
-                    ];
-                    Some(Row::new(data))
+        Tab::Bookmarks => {
+            let current_file = app.files.current_file.clone();
+            let rows = app
+                .bookmarks
+                .of(&current_file)
+                .iter()
+                .map(|bookmark| {
+                    Row::new(vec![
+                        Cell::from(utils::format_duration(bookmark.position)),
+                        Cell::from(bookmark.label.clone()),
+                    ])
                 })
-                .collect();
+                .collect_vec();
 
-            let constraints = &[Constraint::Percentage(80), Constraint::Length(30)];
-            let displayables = Table::new(list_items, constraints)
-                .highlight_style(Style::default().bg(Color::Rgb(51, 51, 51)));
+            let title = Path::new(&current_file)
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let constraints = &[Constraint::Length(10), Constraint::Fill(1)];
+            let bookmarks = Table::new(rows, constraints)
+                .block(
+                    Block::default()
+                        .title(title)
+                        .borders(Borders::ALL)
+                        .border_style(theme.muted),
+                )
+                .highlight_style(theme.selection);
 
             let mut state = TableState::default();
-            state.select(Some(slice_index as usize));
-            f.render_stateful_widget(displayables, chunks[1], &mut state);
-
-            let mode_footer = Paragraph::new(
-                Line::from(app.files.filter.clone())
-                    .style(Style::default().fg(ratatui::style::Color::Rgb(255, 255, 0))),
-            )
+            state.select(Some(app.bookmarks.line_index as usize));
+            f.render_stateful_widget(bookmarks, chunks[1], &mut state);
+
+            let ab_loop = match app.files.ab_loop {
+                (Some(a), Some(b)) => format!(
+                    "A {} B {}",
+                    utils::format_duration(a),
+                    utils::format_duration(b)
+                ),
+                (Some(a), None) => format!("A {}", utils::format_duration(a)),
+                _ => String::default(),
+            };
+            let footer = Paragraph::new(Line::from(vec![
+                Span::from(app.bookmarks.label.clone()).style(theme.accent),
+                Span::from(" "),
+                Span::from(ab_loop).style(theme.muted),
+            ]))
             .block(Block::default().borders(Borders::ALL));
-            f.render_widget(mode_footer, chunks[2]);
+            f.render_widget(footer, chunks[2]);
         }
     }
+
+    draw_now_playing(f, app, chunks[3]);
+    if !chunks[4].is_empty() {
+        draw_up_next(f, &app.files, &app.theme, chunks[4]);
+    }
+
+    if let Some(prompt) = &app.password_prompt {
+        draw_password_prompt(f, prompt, &app.theme, chunks[1]);
+    }
+    if let Some(help) = &app.help {
+        draw_help(f, help, app, chunks[1]);
+    }
+    if let Some(palette) = &app.palette {
+        draw_palette(f, palette, app, chunks[1]);
+    }
+}
+
+fn draw_up_next(f: &mut Frame, files: &Files, theme: &Theme, area: Rect) {
+    let block = Block::default()
+        .title("Up next")
+        .borders(Borders::ALL)
+        .border_style(theme.muted);
+    let lines = match files.play_mode {
+        PlayMode::Random => vec![Line::from("Random order").style(theme.muted)],
+        PlayMode::Queue => files
+            .up_next()
+            .iter()
+            .take(area.height as usize)
+            .map(|line| Line::from(line.dir_entry.file_name().to_string_lossy().to_string()))
+            .collect_vec(),
+    };
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+fn draw_now_playing(f: &mut Frame, app: &mut App, area: Rect) {
+    let theme = &app.theme;
+    let files = &mut app.files;
+    let (state, position) = match files.current_file.is_empty() {
+        true => (SongState::Ended, Duration::ZERO),
+        false => (files.song_state(), files.position()),
+    };
+    let tags = files.current_tags();
+
+    // The member of an archive, or the file
+    let current_file = &files.current_file;
+    let path = archive::split_member(current_file).map_or(current_file.as_str(), |(_, m)| m);
+    let file_name = Path::new(path)
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut title = vec![Span::from(tags.title.clone().unwrap_or(file_name)).bold()];
+    let from = [&tags.artist, &tags.album]
+        .into_iter()
+        .flatten()
+        .join(" · ");
+    if !from.is_empty() {
+        title.push(Span::from(format!(" — {from}")).style(theme.muted));
+    }
+
+    let state_style = match state {
+        SongState::Playing => theme.playing,
+        SongState::Paused => theme.paused,
+        SongState::Ended => theme.muted,
+    };
+    let mut settings = vec![
+        format!("{:?}", files.play_mode).to_lowercase(),
+        format!("volume {:.0}%", files.volume * 100.0),
+    ];
+    if files.speed != 1.0 {
+        settings.push(format!("speed {}x", files.speed));
+    }
+    let duration = tags.duration.filter(|duration| !duration.is_zero());
+    let ratio = match duration {
+        Some(duration) => (position.as_secs_f64() / duration.as_secs_f64()).clamp(0.0, 1.0),
+        None => 0.0,
+    };
+    let label = progress_label(position, duration);
+
+    let block = Block::default()
+        .title(Line::from(state.to_string()).style(state_style.bold()))
+        .title(
+            Line::from(settings.join(" · "))
+                .style(theme.muted)
+                .right_aligned(),
+        )
+        .borders(Borders::ALL)
+        .border_style(theme.muted);
+    f.render_widget(block, area);
+
+    let rows = now_playing_rows(area);
+    f.render_widget(Paragraph::new(Line::from(title)), rows[0]);
+
+    let progress = LineGauge::default()
+        .ratio(ratio)
+        .label(label)
+        .gauge_style(state_style);
+    f.render_widget(progress, rows[1]);
+}
+
+fn draw_palette(f: &mut Frame, palette: &Palette, app: &App, area: Rect) {
+    let theme = &app.theme;
+    let lines = palette.lines(&app.keymap, &app.files.scoring);
+    let width = cmp::min(area.width, 60);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y,
+        width,
+        height: cmp::min(area.height, lines.len() as u16 + 2),
+    };
+
+    let rows = lines
+        .iter()
+        .map(|line| {
+            Row::new(vec![
+                Cell::from(utils::style::string_to_styled_text(
+                    line.action.description(),
+                    &line.indices,
+                    theme.matched,
+                )),
+                Cell::from(Line::from(line.keys.clone()).right_aligned()).style(theme.muted),
+            ])
+        })
+        .collect_vec();
+    let constraints = &[Constraint::Fill(1), Constraint::Length(20)];
+    let table = Table::new(rows, constraints)
+        .block(
+            Block::default()
+                .title(Line::from(vec![
+                    Span::from("> "),
+                    Span::from(palette.filter.clone()).style(theme.accent),
+                ]))
+                .borders(Borders::ALL)
+                .border_style(theme.popup),
+        )
+        .highlight_style(theme.selection);
+
+    let mut state = TableState::default();
+    state.select(Some(palette.line_index));
+    f.render_widget(Clear, popup);
+    f.render_stateful_widget(table, popup, &mut state);
+}
+
+/// Where the help is drawn over `area`.
+pub fn help_area(area: Rect) -> Rect {
+    let width = cmp::min(area.width, 80);
+    let height = area.height.saturating_sub(2);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+fn draw_help(f: &mut Frame, help: &Help, app: &App, area: Rect) {
+    let theme = &app.theme;
+    let popup = help_area(area);
+    let rows = help
+        .lines(&app.keymap)
+        .into_iter()
+        .skip(help.scroll)
+        .map(|[keys, description, context]| {
+            Row::new(vec![
+                Cell::from(keys).style(theme.accent),
+                Cell::from(description),
+                Cell::from(context).style(theme.muted),
+            ])
+        })
+        .collect_vec();
+
+    let filter = match help.filter.is_empty() {
+        true => Line::from("Type to filter"),
+        false => Line::from(help.filter.clone()).style(theme.accent),
+    };
+    let constraints = &[
+        Constraint::Length(14),
+        Constraint::Fill(1),
+        Constraint::Length(11),
+    ];
+    let table = Table::new(rows, constraints).block(
+        Block::default()
+            .title("Keys")
+            .title_bottom(filter)
+            .title_bottom(Line::from("Esc to close").right_aligned())
+            .borders(Borders::ALL)
+            .border_style(theme.popup),
+    );
+    f.render_widget(Clear, popup);
+    f.render_widget(table, popup);
+}
+
+fn draw_password_prompt(f: &mut Frame, prompt: &PasswordPrompt, theme: &Theme, area: Rect) {
+    let file_name = prompt
+        .archive
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let title = match prompt.wrong {
+        true => format!("Wrong password for {file_name}"),
+        false => format!("Password for {file_name}"),
+    };
+    let width = cmp::min(area.width, cmp::max(40, title.len() as u16 + 4));
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + area.height.saturating_sub(3) / 2,
+        width,
+        height: cmp::min(area.height, 3),
+    };
+
+    let password = Paragraph::new("*".repeat(prompt.password.chars().count()))
+        .style(theme.accent)
+        .block(
+            Block::default()
+                .title(title)
+                .title_bottom(Line::from("Enter to extract, Esc to skip").right_aligned())
+                .borders(Borders::ALL)
+                .border_style(theme.prompt),
+        );
+    f.render_widget(Clear, popup);
+    f.render_widget(password, popup);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn side_panel_only_fits_wide_terminals() {
+        let chunks = layout(Rect::new(0, 0, 100, 30));
+        assert_eq!(chunks[1].width, 100);
+        assert!(chunks[4].is_empty());
+
+        let chunks = layout(Rect::new(0, 0, 150, 30));
+        assert_eq!(chunks[1].width, 150 - SIDE_WIDTH);
+        assert_eq!(chunks[4], Rect::new(150 - SIDE_WIDTH, 1, SIDE_WIDTH, 22));
+    }
+
+    #[test]
+    fn clicks_are_mapped_to_what_is_drawn() {
+        let bar = Rect::new(0, 0, 80, 1);
+        // "  Directories  " "  Files  " "  Bookmarks  ", one column apart
+        assert_eq!(tab_at(bar, 0), Some(Tab::Directories));
+        assert_eq!(tab_at(bar, 15), None);
+        assert_eq!(tab_at(bar, 16), Some(Tab::Files));
+        assert_eq!(tab_at(bar, 30), Some(Tab::Bookmarks));
+        assert_eq!(tab_at(bar, 40), None);
+
+        // "00:10 / 01:40" then a space, the bar over the 66 columns left
+        let row = Rect::new(1, 2, 80, 1);
+        let label = progress_label(Duration::from_secs(10), Some(Duration::from_secs(100)));
+        assert_eq!(seek_ratio(row, &label, 14), None);
+        assert_eq!(seek_ratio(row, &label, 15), Some(0.0));
+        assert_eq!(seek_ratio(row, &label, 48), Some(0.5));
+        assert_eq!(seek_ratio(row, &label, 81), None);
+    }
 }
@@ -0,0 +1,655 @@
+use anyhow::{bail, Result};
+use flate2::read::GzDecoder;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use xz2::read::XzDecoder;
+use zip::result::ZipError;
+use zip::CompressionMethod;
+
+/// Bytes read to recognize an archive, enough for the tar header.
+const HEADER_SIZE: usize = 512;
+
+/// Separates an archive from one of its members, as in "album.zip!/disc1/01.flac".
+pub const MEMBER_SEPARATOR: &str = "!/";
+
+pub trait Extractor {
+    /// Extensions of the archives, without the leading dot, e.g. "tar.gz".
+    fn extensions(&self) -> &[&'static str];
+    /// Whether the first bytes of a file belong to this kind of archive.
+    fn matches(&self, header: &[u8]) -> bool;
+    /// Extract `archive` into the `destination` directory, fails with `PasswordRequired`
+    /// if it is encrypted and `password` is missing or wrong.
+    fn extract(
+        &self,
+        archive: Box<dyn ReadSeek>,
+        destination: &Path,
+        password: Option<&str>,
+    ) -> Result<()>;
+}
+
+#[derive(Debug)]
+pub struct PasswordRequired {
+    /// A password was given, but not the right one
+    pub wrong: bool,
+}
+
+impl fmt::Display for PasswordRequired {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.wrong {
+            true => write!(f, "wrong password"),
+            false => write!(f, "password required"),
+        }
+    }
+}
+
+impl std::error::Error for PasswordRequired {}
+
+pub struct Zip;
+
+impl Extractor for Zip {
+    fn extensions(&self) -> &[&'static str] {
+        &["zip"]
+    }
+
+    fn matches(&self, header: &[u8]) -> bool {
+        header.starts_with(b"PK\x03\x04")
+    }
+
+    fn extract(
+        &self,
+        archive: Box<dyn ReadSeek>,
+        destination: &Path,
+        password: Option<&str>,
+    ) -> Result<()> {
+        let mut archive = zip::ZipArchive::new(archive)?;
+
+        for i in 0..archive.len() {
+            let encrypted = archive.by_index_raw(i)?.encrypted();
+            let mut file = match (encrypted, password) {
+                (false, _) => archive.by_index(i)?,
+                (true, None) => return Err(PasswordRequired { wrong: false }.into()),
+                (true, Some(password)) => match archive.by_index_decrypt(i, password.as_bytes()) {
+                    Err(ZipError::InvalidPassword) => {
+                        return Err(PasswordRequired { wrong: true }.into())
+                    }
+                    file => file?,
+                },
+            };
+            // Skip entries escaping the destination
+            let Some(outpath_name) = file.enclosed_name() else {
+                continue;
+            };
+            let outpath = destination.join(outpath_name);
+            log::debug!("Building archive : {:?}", outpath);
+            if file.is_dir() {
+                fs::create_dir_all(&outpath)?;
+            } else {
+                if let Some(p) = outpath.parent() {
+                    fs::create_dir_all(p)?;
+                }
+                let mut outfile = fs::File::create(&outpath)?;
+                io::copy(&mut file, &mut outfile)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Tar {
+    Plain,
+    Gz,
+    Xz,
+    Zst,
+}
+
+impl Extractor for Tar {
+    fn extensions(&self) -> &[&'static str] {
+        match self {
+            Tar::Plain => &["tar"],
+            Tar::Gz => &["tar.gz", "tgz"],
+            Tar::Xz => &["tar.xz", "txz"],
+            Tar::Zst => &["tar.zst", "tzst"],
+        }
+    }
+
+    fn matches(&self, header: &[u8]) -> bool {
+        match self {
+            Tar::Plain => header.get(257..262) == Some(b"ustar"),
+            Tar::Gz => header.starts_with(&[0x1f, 0x8b]),
+            Tar::Xz => header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+            Tar::Zst => header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]),
+        }
+    }
+
+    fn extract(
+        &self,
+        archive: Box<dyn ReadSeek>,
+        destination: &Path,
+        _password: Option<&str>,
+    ) -> Result<()> {
+        let file = io::BufReader::new(archive);
+        let reader: Box<dyn Read> = match self {
+            Tar::Plain => Box::new(file),
+            Tar::Gz => Box::new(GzDecoder::new(file)),
+            Tar::Xz => Box::new(XzDecoder::new(file)),
+            Tar::Zst => Box::new(zstd::Decoder::new(file)?),
+        };
+        fs::create_dir_all(destination)?;
+        // Entries escaping the destination are skipped by unpack
+        tar::Archive::new(reader).unpack(destination)?;
+        Ok(())
+    }
+}
+
+pub struct SevenZip;
+
+impl Extractor for SevenZip {
+    fn extensions(&self) -> &[&'static str] {
+        &["7z"]
+    }
+
+    fn matches(&self, header: &[u8]) -> bool {
+        header.starts_with(&[b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c])
+    }
+
+    fn extract(
+        &self,
+        archive: Box<dyn ReadSeek>,
+        destination: &Path,
+        password: Option<&str>,
+    ) -> Result<()> {
+        let result = match password {
+            Some(password) => {
+                sevenz_rust::decompress_with_password(archive, destination, password.into())
+            }
+            None => sevenz_rust::decompress(archive, destination),
+        };
+        match result {
+            Err(sevenz_rust::Error::PasswordRequired) => {
+                Err(PasswordRequired { wrong: false }.into())
+            }
+            Err(sevenz_rust::Error::MaybeBadPassword(_)) => {
+                Err(PasswordRequired { wrong: true }.into())
+            }
+            result => Ok(result?),
+        }
+    }
+}
+
+/// All known extractors. Compressed tars come before what would only match their compression.
+pub fn extractors() -> Vec<Box<dyn Extractor>> {
+    vec![
+        Box::new(Zip),
+        Box::new(SevenZip),
+        Box::new(Tar::Plain),
+        Box::new(Tar::Gz),
+        Box::new(Tar::Xz),
+        Box::new(Tar::Zst),
+    ]
+}
+
+/// Extension of `path` handled by `extractor`, if any.
+fn matching_extension(extractor: &dyn Extractor, path: &Path) -> Option<&'static str> {
+    let file_name = path.file_name()?.to_string_lossy().to_lowercase();
+    extractor
+        .extensions()
+        .iter()
+        .find(|extension| file_name.ends_with(&format!(".{extension}")))
+        .copied()
+}
+
+/// Extractor for `path`, recognized by its first bytes, or else by its extension.
+pub fn find_extractor(path: &Path) -> Option<Box<dyn Extractor>> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    fs::File::open(path)
+        .ok()?
+        .take(HEADER_SIZE as u64)
+        .read_to_end(&mut header)
+        .ok()?;
+
+    let mut extractors = extractors();
+    let position = extractors
+        .iter()
+        .position(|extractor| extractor.matches(&header))
+        .or_else(|| {
+            extractors
+                .iter()
+                .position(|extractor| matching_extension(extractor.as_ref(), path).is_some())
+        })?;
+    Some(extractors.swap_remove(position))
+}
+
+/// Name of the extraction directory: the file name without its archive extension.
+pub fn archive_name(extractor: &dyn Extractor, path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_string_lossy().to_string();
+    match matching_extension(extractor, path) {
+        Some(extension) => Some(file_name[..file_name.len() - extension.len() - 1].to_string()),
+        None => Some(path.file_stem()?.to_string_lossy().to_string()),
+    }
+}
+
+/// How archives are extracted, shared by the archive workers.
+#[derive(Default)]
+pub struct ArchiveOptions {
+    pub extensions_archives: Vec<String>,
+    /// Archives found in archives are extracted up to this depth
+    pub max_depth: usize,
+    /// Size cap of the cache directory in bytes
+    pub max_size: Option<u64>,
+    /// Passwords by archive path or file name
+    pub passwords: RwLock<HashMap<String, String>>,
+}
+
+impl ArchiveOptions {
+    pub fn password(&self, archive: &Path) -> Option<String> {
+        let passwords = self.passwords.read().unwrap();
+        let file_name = archive.file_name()?.to_string_lossy();
+        passwords
+            .get(archive.to_string_lossy().as_ref())
+            .or_else(|| passwords.get(file_name.as_ref()))
+            .cloned()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct PasswordRequest {
+    pub archive: PathBuf,
+    /// The last password was wrong
+    pub wrong: bool,
+}
+
+/// What the archive thread is doing, shown in the Files tab.
+#[derive(Clone, Default, Debug)]
+pub struct ArchiveStatus {
+    /// Archive being extracted, with the part of it read so far
+    pub extracting: Option<(String, f32)>,
+    /// Archives that could not be extracted, with the reason
+    pub failed: Vec<(String, String)>,
+    /// Encrypted archives to prompt a password for
+    pub password_requests: Vec<PasswordRequest>,
+}
+
+impl fmt::Display for ArchiveStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((archive, progress)) = &self.extracting {
+            write!(f, "Extracting {archive} {:.0}%", progress * 100.0)?;
+            if !self.failed.is_empty() {
+                write!(f, ", ")?;
+            }
+        }
+        match self.failed.as_slice() {
+            [] => Ok(()),
+            [(archive, error)] => write!(f, "Could not extract {archive}: {error}"),
+            failed => write!(f, "Could not extract {} archives", failed.len()),
+        }
+    }
+}
+
+/// Reader reporting how much of the archive was read to `status`.
+pub struct Progress<R> {
+    inner: R,
+    name: String,
+    read: u64,
+    size: u64,
+    status: Arc<RwLock<ArchiveStatus>>,
+}
+
+impl<R> Progress<R> {
+    pub fn new(inner: R, name: &str, size: u64, status: Arc<RwLock<ArchiveStatus>>) -> Self {
+        status.write().unwrap().extracting = Some((name.to_string(), 0.0));
+        Self {
+            inner,
+            name: name.to_string(),
+            read: 0,
+            size: size.max(1),
+            status,
+        }
+    }
+}
+
+impl<R> Drop for Progress<R> {
+    fn drop(&mut self) {
+        self.status.write().unwrap().extracting = None;
+    }
+}
+
+impl<R: Read> Read for Progress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let before = self.read * 100 / self.size;
+        self.read += read as u64;
+        // Only report whole percents
+        if self.read * 100 / self.size != before {
+            let progress = (self.read as f32 / self.size as f32).min(1.0);
+            self.status.write().unwrap().extracting = Some((self.name.clone(), progress));
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for Progress<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+// Archive members can be played without extracting them. Only zip archives
+// are listed this way, as their members can be read on their own.
+
+pub trait ReadSeek: Read + Seek + Send + Sync {}
+
+impl<T: Read + Seek + Send + Sync> ReadSeek for T {}
+
+/// Archive and member of "archive.zip!/member", if `file_name` is a member of an
+/// existing zip archive. Directory names may hold the separator too.
+pub fn split_member(file_name: &str) -> Option<(&str, &str)> {
+    file_name
+        .match_indices(MEMBER_SEPARATOR)
+        .map(|(i, separator)| (&file_name[..i], &file_name[i + separator.len()..]))
+        .find(|(archive, _)| archive.ends_with(".zip") && Path::new(archive).is_file())
+}
+
+pub fn member_path(archive: &Path, member: &str) -> String {
+    format!("{}{MEMBER_SEPARATOR}{member}", archive.to_string_lossy())
+}
+
+/// Members of a zip archive with one of `extensions`, that can be opened with `open_member`.
+pub fn list_members(archive: &Path, extensions: &[String]) -> Result<Vec<String>> {
+    let mut archive = zip::ZipArchive::new(io::BufReader::new(fs::File::open(archive)?))?;
+    let mut members = vec![];
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        let playable = file.is_file()
+            && !file.encrypted()
+            && matches!(
+                file.compression(),
+                CompressionMethod::Stored | CompressionMethod::Deflated
+            )
+            && extensions
+                .iter()
+                .any(|extension| file.name().ends_with(&format!(".{extension}")));
+        if playable {
+            members.push(file.name().to_string());
+        }
+    }
+    Ok(members)
+}
+
+/// Reader of a zip archive member. Stored members are read in place, deflated ones are
+/// inflated in memory, as decoders need to seek.
+pub fn open_member(archive: &Path, member: &str) -> Result<Box<dyn ReadSeek>> {
+    let mut archive = zip::ZipArchive::new(io::BufReader::new(fs::File::open(archive)?))?;
+    let mut file = archive.by_name(member)?;
+    match file.compression() {
+        CompressionMethod::Stored => {
+            let (start, length) = (file.data_start(), file.size());
+            drop(file);
+            Ok(Box::new(Window::new(archive.into_inner(), start, length)?))
+        }
+        CompressionMethod::Deflated => {
+            let mut data = Vec::with_capacity(file.size() as usize);
+            file.read_to_end(&mut data)?;
+            Ok(Box::new(Cursor::new(data)))
+        }
+        method => bail!("Unsupported compression {method} for {member}"),
+    }
+}
+
+/// `length` bytes of `inner` from `start`, seen as a whole reader.
+struct Window<R> {
+    inner: R,
+    start: u64,
+    length: u64,
+    position: u64,
+}
+
+impl<R: Seek> Window<R> {
+    fn new(mut inner: R, start: u64, length: u64) -> io::Result<Self> {
+        inner.seek(SeekFrom::Start(start))?;
+        Ok(Self {
+            inner,
+            start,
+            length,
+            position: 0,
+        })
+    }
+}
+
+impl<R: Read> Read for Window<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.length.saturating_sub(self.position);
+        let size = (buf.len() as u64).min(remaining) as usize;
+        let read = self.inner.read(&mut buf[..size])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for Window<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        let Some(position) = position else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the start of the member",
+            ));
+        };
+        self.inner.seek(SeekFrom::Start(self.start + position))?;
+        self.position = position;
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    fn write_tar<W: Write>(writer: W) -> W {
+        let mut builder = tar::Builder::new(writer);
+        let content = b"song";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "disc1/01.flac", &content[..])
+            .unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    fn create_archives(dir: &Path) {
+        let file = fs::File::create(dir.join("album.tar")).unwrap();
+        write_tar(file);
+
+        let file = fs::File::create(dir.join("album.tar.gz")).unwrap();
+        let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        write_tar(encoder).finish().unwrap();
+
+        let file = fs::File::create(dir.join("album.tar.xz")).unwrap();
+        write_tar(xz2::write::XzEncoder::new(file, 6))
+            .finish()
+            .unwrap();
+
+        let file = fs::File::create(dir.join("album.tzst")).unwrap();
+        write_tar(zstd::Encoder::new(file, 0).unwrap())
+            .finish()
+            .unwrap();
+
+        let file = fs::File::create(dir.join("album.zip")).unwrap();
+        let mut writer = zip::ZipWriter::new(file);
+        writer
+            .start_file("disc1/01.flac", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"song").unwrap();
+        writer.finish().unwrap();
+
+        let source = dir.join("source");
+        fs::create_dir_all(source.join("disc1")).unwrap();
+        fs::write(source.join("disc1/01.flac"), b"song").unwrap();
+        sevenz_rust::compress_to_path(&source, dir.join("album.7z")).unwrap();
+    }
+
+    #[test]
+    fn archives_are_extracted() {
+        let dir = TempDir::new().unwrap();
+        create_archives(dir.path());
+
+        for file_name in [
+            "album.tar",
+            "album.tar.gz",
+            "album.tar.xz",
+            "album.tzst",
+            "album.zip",
+            "album.7z",
+        ] {
+            let archive = dir.path().join(file_name);
+            let extractor = find_extractor(&archive).unwrap();
+            assert_eq!(archive_name(extractor.as_ref(), &archive).unwrap(), "album");
+
+            let destination = dir.path().join("out").join(file_name);
+            let reader = Box::new(fs::File::open(&archive).unwrap());
+            extractor.extract(reader, &destination, None).unwrap();
+            assert_eq!(
+                fs::read(destination.join("disc1/01.flac")).unwrap(),
+                b"song",
+                "{file_name}"
+            );
+        }
+    }
+
+    #[test]
+    fn magic_bytes_win_over_extension() {
+        let dir = TempDir::new().unwrap();
+        create_archives(dir.path());
+        let archive = dir.path().join("album.zip.bin");
+        fs::rename(dir.path().join("album.tar.gz"), &archive).unwrap();
+
+        let extractor = find_extractor(&archive).unwrap();
+        assert_eq!(extractor.extensions(), Tar::Gz.extensions());
+        assert_eq!(
+            archive_name(extractor.as_ref(), &archive).unwrap(),
+            "album.zip"
+        );
+    }
+
+    #[test]
+    fn unknown_files_have_no_extractor() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, b"not an archive").unwrap();
+        assert!(find_extractor(&path).is_none());
+    }
+
+    #[test]
+    fn stored_and_deflated_members_are_read_in_place() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("album.zip");
+        let song = (0..10_000).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        let mut writer = zip::ZipWriter::new(fs::File::create(&archive).unwrap());
+        for (name, method) in [
+            ("stored.flac", CompressionMethod::Stored),
+            ("deflated.flac", CompressionMethod::Deflated),
+        ] {
+            let options = zip::write::SimpleFileOptions::default().compression_method(method);
+            writer.start_file(name, options).unwrap();
+            writer.write_all(&song).unwrap();
+        }
+        writer
+            .start_file("cover.jpg", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.finish().unwrap();
+
+        let members = list_members(&archive, &[String::from("flac")]).unwrap();
+        assert_eq!(members, ["stored.flac", "deflated.flac"]);
+
+        for member in members {
+            let mut reader = open_member(&archive, &member).unwrap();
+            let mut content = vec![];
+            reader.read_to_end(&mut content).unwrap();
+            assert_eq!(content, song, "{member}");
+
+            assert_eq!(reader.seek(SeekFrom::End(-10)).unwrap(), 9_990);
+            let mut end = vec![];
+            reader.read_to_end(&mut end).unwrap();
+            assert_eq!(end, song[9_990..]);
+            assert!(reader.seek(SeekFrom::Current(-20_000)).is_err());
+        }
+    }
+
+    #[test]
+    fn members_are_split_from_their_archive() {
+        let dir = TempDir::new().unwrap();
+        let music = dir.path().join("Help!");
+        fs::create_dir_all(music.join("live")).unwrap();
+        fs::write(music.join("live/01.flac"), b"song").unwrap();
+        let archive = music.join("album.zip");
+        fs::write(&archive, b"zip").unwrap();
+        let music = music.to_str().unwrap();
+
+        let path = member_path(&archive, "disc1/01.flac");
+        assert_eq!(path, format!("{music}/album.zip!/disc1/01.flac"));
+        assert_eq!(
+            split_member(&path),
+            Some((archive.to_str().unwrap(), "disc1/01.flac"))
+        );
+        assert_eq!(split_member(&format!("{music}/live/01.flac")), None);
+        assert_eq!(split_member("/music/missing.zip!/01.flac"), None);
+    }
+
+    #[test]
+    fn progress_is_reported_while_reading() {
+        let status = Arc::new(RwLock::new(ArchiveStatus::default()));
+        let data = vec![0u8; 1000];
+        let mut reader = Progress::new(Cursor::new(data), "album.zip", 1000, status.clone());
+        assert_eq!(
+            status.read().unwrap().to_string(),
+            "Extracting album.zip 0%"
+        );
+
+        reader.read_exact(&mut [0u8; 420]).unwrap();
+        assert_eq!(
+            status.read().unwrap().to_string(),
+            "Extracting album.zip 42%"
+        );
+
+        drop(reader);
+        assert_eq!(status.read().unwrap().to_string(), "");
+    }
+
+    #[test]
+    fn encrypted_seven_zip_needs_the_right_password() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("source");
+        fs::create_dir(&source).unwrap();
+        fs::write(source.join("01.flac"), b"song").unwrap();
+        let archive = dir.path().join("secret.7z");
+        sevenz_rust::compress_to_path_encrypted(&source, &archive, "password".into()).unwrap();
+
+        for (password, wrong) in [(None, false), (Some("wrong"), true)] {
+            let reader = Box::new(fs::File::open(&archive).unwrap());
+            let error = SevenZip
+                .extract(reader, &dir.path().join("out"), password)
+                .unwrap_err();
+            let error = error.downcast_ref::<PasswordRequired>().unwrap();
+            assert_eq!(error.wrong, wrong);
+        }
+
+        let reader = Box::new(fs::File::open(&archive).unwrap());
+        let destination = dir.path().join("out");
+        SevenZip
+            .extract(reader, &destination, Some("password"))
+            .unwrap();
+        assert_eq!(fs::read(destination.join("01.flac")).unwrap(), b"song");
+    }
+}
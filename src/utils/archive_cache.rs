@@ -0,0 +1,544 @@
+use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use walkdir::WalkDir;
+
+// Extracted archives are recorded in an index, with the archive they come
+// from. Play history is kept in another file, as it is written when playing
+// files while the index is written by the archive threads. Both are kept out of
+// the cache directory, which is watched as a working directory. Folders missing
+// from the index are left alone.
+
+static INDEX_PATH: &str = "archives.json";
+static PLAYED_PATH: &str = "archives_played.json";
+
+/// What an extracted folder was extracted from.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Provenance {
+    pub source: String,
+    pub size: u64,
+    pub modified: SystemTime,
+    /// Sha256 of the archive, only computed when size or modification time change.
+    pub hash: String,
+}
+
+impl Provenance {
+    /// Provenance of `source`, reusing the hash of `previous` if the file looks unchanged.
+    pub fn of(source: &Path, previous: Option<&Provenance>) -> Result<Provenance> {
+        let metadata = fs::metadata(source)?;
+        let source = source.to_string_lossy().to_string();
+        let (size, modified) = (metadata.len(), metadata.modified()?);
+        if let Some(previous) = previous.filter(|previous| {
+            previous.source == source && previous.size == size && previous.modified == modified
+        }) {
+            return Ok(previous.clone());
+        }
+
+        let mut hasher = Sha256::new();
+        io::copy(&mut BufReader::new(File::open(&source)?), &mut hasher)?;
+        Ok(Provenance {
+            source,
+            size,
+            modified,
+            hash: format!("{:x}", hasher.finalize()),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CacheEntry {
+    pub provenance: Provenance,
+    /// None once evicted or failed. Failed archives are not extracted again until
+    /// they change, evicted ones once they fit in the cache.
+    pub extracted: Option<SystemTime>,
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Size of the folder when it was evicted
+    #[serde(default)]
+    pub size: u64,
+}
+
+/// Extracted folders of the cache directory, by folder name.
+pub struct ArchiveCache {
+    dir: PathBuf,
+    // Of the index and the play history
+    index_dir: PathBuf,
+    pub entries: HashMap<String, CacheEntry>,
+}
+
+impl ArchiveCache {
+    /// Folders of `dir`, indexed in `index_dir`.
+    pub fn load(dir: &Path, index_dir: &Path) -> ArchiveCache {
+        // Older versions kept them in the cache directory
+        for file_name in [INDEX_PATH, PLAYED_PATH] {
+            super::move_legacy_file(&dir.join(file_name), &index_dir.join(file_name));
+        }
+        let entries = File::open(index_dir.join(INDEX_PATH))
+            .ok()
+            .and_then(|file| serde_json::from_reader(file).ok())
+            .unwrap_or_default();
+        ArchiveCache {
+            dir: dir.to_path_buf(),
+            index_dir: index_dir.to_path_buf(),
+            entries,
+        }
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = write_json(&self.index_dir.join(INDEX_PATH), &self.entries) {
+            log::error!("Could not save archive cache index: {e}");
+        }
+    }
+
+    /// Provenance of what was last extracted to `name`.
+    pub fn provenance(&self, name: &str) -> Option<Provenance> {
+        self.entries.get(name).map(|entry| entry.provenance.clone())
+    }
+
+    /// Whether `provenance` has to be extracted to `name`, in a cache of at most `max_size` bytes.
+    pub fn outdated(&mut self, name: &str, provenance: &Provenance, max_size: Option<u64>) -> bool {
+        let Some(entry) = self.entries.get_mut(name) else {
+            return true;
+        };
+        if entry.provenance.source != provenance.source {
+            // Another archive with the same name got there first
+            if Path::new(&entry.provenance.source).exists() {
+                log::debug!("{:?} is already extracted from another archive", name);
+                return false;
+            }
+            return true;
+        }
+        if entry.provenance.hash != provenance.hash {
+            return true;
+        }
+        // Only touched, no need to hash it again
+        entry.provenance = provenance.clone();
+        if entry.extracted.is_some() || entry.error.is_some() {
+            return false;
+        }
+        // Evicted, back once there is room for it
+        let evicted_size = entry.size;
+        max_size.is_none_or(|max_size| self.size() + evicted_size <= max_size)
+    }
+
+    /// Size of the extracted folders.
+    fn size(&self) -> u64 {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.extracted.is_some())
+            .map(|(name, _)| folder_size(&self.dir.join(name)))
+            .sum()
+    }
+
+    pub fn insert(&mut self, name: &str, provenance: Provenance) {
+        self.entries.insert(
+            name.to_string(),
+            CacheEntry {
+                provenance,
+                extracted: Some(SystemTime::now()),
+                error: None,
+                size: 0,
+            },
+        );
+    }
+
+    /// Record that `provenance` could not be extracted to `name`.
+    pub fn insert_failure(&mut self, name: &str, provenance: Provenance, error: String) {
+        self.entries.insert(
+            name.to_string(),
+            CacheEntry {
+                provenance,
+                extracted: None,
+                error: Some(error),
+                size: 0,
+            },
+        );
+    }
+
+    /// Forget that `source` could not be extracted, so that it is tried again.
+    pub fn forget_failure(&mut self, source: &str) {
+        self.entries
+            .retain(|_, entry| entry.error.is_none() || entry.provenance.source != source);
+    }
+
+    /// Archives that could not be extracted, with the reason.
+    pub fn failures(&self) -> Vec<(String, String)> {
+        let mut failures = self
+            .entries
+            .values()
+            .filter_map(|entry| {
+                let name = Path::new(&entry.provenance.source).file_name()?;
+                Some((name.to_string_lossy().to_string(), entry.error.clone()?))
+            })
+            .collect::<Vec<(String, String)>>();
+        failures.sort();
+        failures
+    }
+
+    /// Remove folders whose archive does not exist anymore.
+    pub fn remove_orphans(&mut self) {
+        let orphans = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| !Path::new(&entry.provenance.source).exists())
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<String>>();
+        for name in orphans {
+            log::info!("Removing {:?}, its archive is gone", name);
+            remove_folder(&self.dir.join(&name));
+            self.entries.remove(&name);
+        }
+    }
+
+    /// Remove every folder, and forget failures.
+    pub fn clear(&mut self) {
+        for name in self.entries.keys() {
+            log::info!("Removing {:?} from the archive cache", name);
+            remove_folder(&self.dir.join(name));
+        }
+        self.entries.clear();
+    }
+
+    /// Remove the least recently used folders until the cache fits in `max_size` bytes.
+    /// The most recently used folder is always kept.
+    pub fn evict(&mut self, max_size: u64) {
+        let played = played(&self.index_dir);
+        let mut folders = self
+            .entries
+            .iter()
+            .filter_map(|(name, entry)| {
+                let extracted = entry.extracted?;
+                let last_used = played
+                    .get(name)
+                    .map_or(extracted, |played| extracted.max(*played));
+                Some((name.clone(), last_used, folder_size(&self.dir.join(name))))
+            })
+            .collect::<Vec<(String, SystemTime, u64)>>();
+        let mut size = folders.iter().map(|(_, _, size)| size).sum::<u64>();
+        folders.sort_by_key(|(_, last_used, _)| *last_used);
+        folders.pop();
+
+        for (name, _, folder_size) in folders {
+            if size <= max_size {
+                break;
+            }
+            log::info!("Evicting {:?} from the archive cache", name);
+            remove_folder(&self.dir.join(&name));
+            size -= folder_size;
+            if let Some(entry) = self.entries.get_mut(&name) {
+                entry.extracted = None;
+                entry.size = folder_size;
+            }
+        }
+    }
+}
+
+/// Record in `index_dir` that `file_name` was played, if it was extracted to `cache_dir`.
+pub fn touch(cache_dir: &Path, index_dir: &Path, file_name: &str) {
+    let Ok(relative) = Path::new(file_name).strip_prefix(cache_dir) else {
+        return;
+    };
+    let Some(Component::Normal(name)) = relative.components().next() else {
+        return;
+    };
+    let mut played = played(index_dir);
+    played.insert(name.to_string_lossy().to_string(), SystemTime::now());
+    if let Err(e) = write_json(&index_dir.join(PLAYED_PATH), &played) {
+        log::error!("Could not save archive play history: {e}");
+    }
+}
+
+fn played(index_dir: &Path) -> HashMap<String, SystemTime> {
+    File::open(index_dir.join(PLAYED_PATH))
+        .ok()
+        .and_then(|file| serde_json::from_reader(file).ok())
+        .unwrap_or_default()
+}
+
+/// Written whole, as the play history is read by the archive threads while it is written.
+fn write_json<T: serde::Serialize>(path: &Path, value: &T) -> Result<()> {
+    super::write_atomic(path, &serde_json::to_vec_pretty(value)?)?;
+    Ok(())
+}
+
+pub fn remove_folder(folder: &Path) {
+    if let Err(e) = fs::remove_dir_all(folder) {
+        if e.kind() != io::ErrorKind::NotFound {
+            log::error!("Could not remove {:?}: {e}", folder);
+        }
+    }
+}
+
+fn folder_size(folder: &Path) -> u64 {
+    WalkDir::new(folder)
+        .into_iter()
+        .filter_map(|entry| entry.ok()?.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::archive::{ArchiveOptions, ArchiveStatus, PasswordRequest};
+    use crate::utils::directory::{clean_archive_cache, find_archives, process_archive};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex, RwLock};
+    use tempfile::TempDir;
+
+    struct Setup {
+        music: TempDir,
+        cache: TempDir,
+        index: TempDir,
+        options: ArchiveOptions,
+        status: Arc<RwLock<ArchiveStatus>>,
+    }
+
+    fn zip(members: &[(&str, &[u8])], password: Option<&str>) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(vec![]));
+        for (name, content) in members {
+            let mut options = zip::write::SimpleFileOptions::default();
+            if let Some(password) = password {
+                options = options.with_aes_encryption(zip::AesMode::Aes256, password);
+            }
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    impl Setup {
+        fn new() -> Setup {
+            Setup {
+                music: TempDir::new().unwrap(),
+                cache: TempDir::new().unwrap(),
+                index: TempDir::new().unwrap(),
+                options: ArchiveOptions {
+                    extensions_archives: vec![String::from("zip")],
+                    ..ArchiveOptions::default()
+                },
+                status: Arc::new(RwLock::new(ArchiveStatus::default())),
+            }
+        }
+
+        fn write_zip(&self, name: &str, content: &[u8]) {
+            let zip = zip(&[("01.flac", content)], None);
+            fs::write(self.music.path().join(name), zip).unwrap();
+        }
+
+        fn load(&self) -> ArchiveCache {
+            ArchiveCache::load(self.cache.path(), self.index.path())
+        }
+
+        fn process(&self, max_size: Option<u64>) {
+            let paths = [self.music.path().to_string_lossy().to_string()];
+            let cache_dir = self.cache.path();
+            let cache = Mutex::new(self.load());
+            let options = ArchiveOptions {
+                extensions_archives: self.options.extensions_archives.clone(),
+                max_depth: self.options.max_depth,
+                max_size,
+                passwords: RwLock::new(self.options.passwords.read().unwrap().clone()),
+            };
+            for archive in find_archives(&paths, &options.extensions_archives, cache_dir) {
+                process_archive(&archive, cache_dir, &cache, &options, &self.status);
+            }
+            clean_archive_cache(&cache, max_size, &self.status);
+        }
+
+        fn extracted(&self, name: &str) -> Option<Vec<u8>> {
+            fs::read(self.cache.path().join(name).join("01.flac")).ok()
+        }
+    }
+
+    #[test]
+    fn cleared_archives_are_extracted_again() {
+        let setup = Setup::new();
+        setup.write_zip("album.zip", b"first");
+        setup.process(None);
+
+        let mut cache = setup.load();
+        cache.clear();
+        cache.save();
+        assert_eq!(setup.extracted("album"), None);
+
+        setup.process(None);
+        assert_eq!(setup.extracted("album").unwrap(), b"first");
+    }
+
+    #[test]
+    fn changed_archives_are_extracted_again() {
+        let setup = Setup::new();
+        setup.write_zip("album.zip", b"first");
+        setup.process(None);
+        assert_eq!(setup.extracted("album").unwrap(), b"first");
+
+        // Unchanged, left as is
+        fs::write(setup.cache.path().join("album/01.flac"), b"edited").unwrap();
+        setup.process(None);
+        assert_eq!(setup.extracted("album").unwrap(), b"edited");
+
+        setup.write_zip("album.zip", b"second version");
+        setup.process(None);
+        assert_eq!(setup.extracted("album").unwrap(), b"second version");
+
+        let cache = setup.load();
+        let provenance = &cache.entries["album"].provenance;
+        assert_eq!(
+            provenance.source,
+            setup.music.path().join("album.zip").to_string_lossy()
+        );
+        assert_eq!(provenance.hash.len(), 64);
+    }
+
+    #[test]
+    fn orphans_are_removed() {
+        let setup = Setup::new();
+        setup.write_zip("album.zip", b"song");
+        fs::create_dir(setup.cache.path().join("unknown")).unwrap();
+        setup.process(None);
+
+        fs::remove_file(setup.music.path().join("album.zip")).unwrap();
+        setup.process(None);
+        assert!(!setup.cache.path().join("album").exists());
+        assert!(setup.cache.path().join("unknown").exists());
+        assert!(setup.load().entries.is_empty());
+        assert!(!setup.cache.path().join(INDEX_PATH).exists());
+    }
+
+    #[test]
+    fn least_recently_played_folders_are_evicted() {
+        let setup = Setup::new();
+        for name in ["a.zip", "b.zip", "c.zip"] {
+            setup.write_zip(name, b"song");
+            setup.process(None);
+        }
+        let played = setup.cache.path().join("a/01.flac");
+        touch(
+            setup.cache.path(),
+            setup.index.path(),
+            &played.to_string_lossy(),
+        );
+
+        // Room for two folders
+        setup.process(Some(8));
+        assert!(setup.extracted("a").is_some());
+        assert!(setup.extracted("b").is_none());
+        assert!(setup.extracted("c").is_some());
+
+        // Extracted again once there is room for it
+        setup.process(Some(8));
+        assert!(setup.extracted("b").is_none());
+        setup.process(Some(12));
+        assert_eq!(setup.extracted("b").unwrap(), b"song");
+        assert!(setup.extracted("a").is_some());
+        assert!(setup.extracted("c").is_some());
+    }
+
+    #[test]
+    fn failed_archives_are_reported_and_not_retried() {
+        let setup = Setup::new();
+        let archive = setup.music.path().join("album.zip");
+        fs::write(&archive, b"PK\x03\x04 truncated").unwrap();
+        setup.process(None);
+
+        let cache_dir = setup.cache.path();
+        assert!(!cache_dir.join("album").exists());
+        assert!(!cache_dir.with_extension("partial").exists());
+        let failed = setup.status.read().unwrap().failed.clone();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, "album.zip");
+        assert!(setup.status.read().unwrap().extracting.is_none());
+
+        // Not retried while unchanged
+        let modified = fs::metadata(&archive).unwrap().modified().unwrap();
+        let mut cache = setup.load();
+        let provenance = Provenance::of(&archive, cache.provenance("album").as_ref()).unwrap();
+        assert!(!cache.outdated("album", &provenance, None));
+        assert_eq!(cache.entries["album"].provenance.modified, modified);
+
+        setup.write_zip("album.zip", b"song");
+        setup.process(None);
+        assert_eq!(setup.extracted("album").unwrap(), b"song");
+        assert!(setup.status.read().unwrap().failed.is_empty());
+    }
+
+    #[test]
+    fn encrypted_archives_wait_for_a_password() {
+        let setup = Setup::new();
+        let archive = setup.music.path().join("secret.zip");
+        fs::write(&archive, zip(&[("01.flac", b"song")], Some("password"))).unwrap();
+        setup.process(None);
+
+        assert!(!setup.cache.path().join("secret").exists());
+        let status = setup.status.read().unwrap().clone();
+        assert_eq!(
+            status.to_string(),
+            "Could not extract secret.zip: password required"
+        );
+        assert_eq!(
+            status.password_requests,
+            [PasswordRequest {
+                archive: archive.clone(),
+                wrong: false
+            }]
+        );
+
+        let source = archive.to_string_lossy().to_string();
+        let mut passwords = setup.options.passwords.write().unwrap();
+        passwords.insert(String::from("secret.zip"), String::from("wrong"));
+        drop(passwords);
+        let mut cache = setup.load();
+        cache.forget_failure(&source);
+        cache.save();
+        setup.process(None);
+        assert!(setup.status.read().unwrap().password_requests[0].wrong);
+
+        let mut passwords = setup.options.passwords.write().unwrap();
+        passwords.insert(source.clone(), String::from("password"));
+        drop(passwords);
+        let mut cache = setup.load();
+        cache.forget_failure(&source);
+        cache.save();
+        setup.process(None);
+        assert_eq!(setup.extracted("secret").unwrap(), b"song");
+        assert!(setup.status.read().unwrap().password_requests.is_empty());
+    }
+
+    #[test]
+    fn nested_archives_are_extracted_up_to_max_depth() {
+        let mut setup = Setup::new();
+        let disc = zip(&[("01.flac", b"song")], Some("password"));
+        let box_set = zip(&[("box/disc1.zip", &disc)], None);
+        fs::write(
+            setup.music.path().join("bundle.zip"),
+            zip(&[("box.zip", &box_set)], None),
+        )
+        .unwrap();
+        let passwords = HashMap::from([(String::from("bundle.zip"), String::from("password"))]);
+        setup.options.passwords = RwLock::new(passwords);
+
+        setup.process(None);
+        let bundle = setup.cache.path().join("bundle");
+        assert!(bundle.join("box.zip").exists());
+
+        fs::remove_dir_all(&bundle).unwrap();
+        let mut cache = setup.load();
+        cache.entries.clear();
+        cache.save();
+        setup.options.max_depth = 2;
+        setup.process(None);
+        assert_eq!(
+            fs::read(bundle.join("box/box/disc1/01.flac")).unwrap(),
+            b"song"
+        );
+        assert!(!bundle.join("box.zip").exists());
+        assert!(!bundle.join("box/box/disc1.zip").exists());
+    }
+}
@@ -0,0 +1,260 @@
+use super::archive::{ArchiveOptions, ArchiveStatus};
+use super::archive_cache::ArchiveCache;
+use super::directory;
+use crossbeam_channel::{unbounded, Sender};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+
+// Archives are extracted by a few threads, fed with the archives of the
+// directory watcher events, and with the archives found when working paths
+// change. An archive is queued once, however many events it gets before a
+// worker picks it up, and is extracted by one worker at a time: events during
+// its extraction have the same worker extract it again once done.
+
+#[derive(Default)]
+struct Queue {
+    // Sent to the workers
+    waiting: HashSet<PathBuf>,
+    // Being extracted, and whether to extract it again once done
+    running: HashMap<PathBuf, bool>,
+}
+
+/// Clones feed the same workers.
+#[derive(Clone)]
+pub struct ArchiveWorkers {
+    sender: Sender<PathBuf>,
+    queue: Arc<Mutex<Queue>>,
+    cache: Arc<Mutex<ArchiveCache>>,
+    options: Arc<ArchiveOptions>,
+    status: Arc<RwLock<ArchiveStatus>>,
+    cache_dir: PathBuf,
+}
+
+impl ArchiveWorkers {
+    /// Workers stop once every clone is dropped.
+    pub fn start(
+        workers: usize,
+        options: ArchiveOptions,
+        cache_dir: PathBuf,
+        index_dir: &Path,
+        status: Arc<RwLock<ArchiveStatus>>,
+    ) -> ArchiveWorkers {
+        let options = Arc::new(options);
+        let (sender, receiver) = unbounded::<PathBuf>();
+        let queue = Arc::new(Mutex::new(Queue::default()));
+        let cache = Arc::new(Mutex::new(ArchiveCache::load(&cache_dir, index_dir)));
+
+        for _ in 0..workers {
+            let receiver = receiver.clone();
+            let queue = queue.clone();
+            let cache = cache.clone();
+            let cache_dir = cache_dir.clone();
+            let options = options.clone();
+            let status = status.clone();
+            thread::spawn(move || {
+                for archive in receiver.iter() {
+                    let mut locked = queue.lock().unwrap();
+                    locked.waiting.remove(&archive);
+                    locked.running.insert(archive.clone(), false);
+                    drop(locked);
+                    loop {
+                        directory::process_archive(&archive, &cache_dir, &cache, &options, &status);
+                        directory::clean_archive_cache(&cache, options.max_size, &status);
+                        let mut locked = queue.lock().unwrap();
+                        match locked.running.get_mut(&archive) {
+                            Some(again) if *again => *again = false,
+                            _ => {
+                                locked.running.remove(&archive);
+                                break;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+
+        ArchiveWorkers {
+            sender,
+            queue,
+            cache,
+            options,
+            status,
+            cache_dir,
+        }
+    }
+
+    /// Extract `archive` again, with `password`, kept until exit.
+    pub fn unlock(&self, archive: &Path, password: &str) {
+        let source = archive.to_string_lossy().to_string();
+        let mut passwords = self.options.passwords.write().unwrap();
+        passwords.insert(source.clone(), password.to_string());
+        drop(passwords);
+
+        self.cache.lock().unwrap().forget_failure(&source);
+        let mut status = self.status.write().unwrap();
+        status
+            .password_requests
+            .retain(|request| request.archive != archive);
+        drop(status);
+        self.queue(archive);
+    }
+
+    /// Remove every extracted folder of the cache.
+    pub fn clear_cache(&self) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.clear();
+        cache.save();
+        self.status.write().unwrap().failed = cache.failures();
+    }
+
+    /// Queue `path` if it is an archive, even a removed one, out of the cache directory.
+    pub fn queue(&self, path: &Path) {
+        if path.starts_with(&self.cache_dir)
+            || !directory::has_extension(path, &self.options.extensions_archives)
+        {
+            return;
+        }
+        let mut queue = self.queue.lock().unwrap();
+        if let Some(again) = queue.running.get_mut(path) {
+            *again = true;
+            return;
+        }
+        if queue.waiting.insert(path.to_path_buf()) {
+            log::debug!("Queueing archive : {:?}", path);
+            if let Err(e) = self.sender.send(path.to_path_buf()) {
+                log::error!("Could not queue {:?}: {e}", path);
+            }
+        }
+    }
+
+    /// Queue every archive of `paths`.
+    pub fn scan(&self, paths: &[String]) {
+        let extensions_archives = &self.options.extensions_archives;
+        for archive in directory::find_archives(paths, extensions_archives, &self.cache_dir) {
+            self.queue(&archive);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    fn write_zip(path: &Path) {
+        let mut writer = zip::ZipWriter::new(File::create(path).unwrap());
+        writer
+            .start_file("01.flac", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"song").unwrap();
+        writer.finish().unwrap();
+    }
+
+    fn options() -> ArchiveOptions {
+        ArchiveOptions {
+            extensions_archives: vec![String::from("zip")],
+            ..ArchiveOptions::default()
+        }
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn queued_archives_are_extracted_and_removed() {
+        let music = TempDir::new().unwrap();
+        let cache = TempDir::new().unwrap();
+        let index = TempDir::new().unwrap();
+        let status = Arc::new(RwLock::new(ArchiveStatus::default()));
+        let workers = ArchiveWorkers::start(
+            2,
+            options(),
+            cache.path().to_path_buf(),
+            index.path(),
+            status,
+        );
+
+        write_zip(&music.path().join("a.zip"));
+        workers.scan(&[music.path().to_string_lossy().to_string()]);
+        wait_for(|| cache.path().join("a/01.flac").exists());
+
+        let archive = music.path().join("b.zip");
+        write_zip(&archive);
+        workers.queue(&archive);
+        wait_for(|| cache.path().join("b/01.flac").exists());
+
+        fs::remove_file(&archive).unwrap();
+        workers.queue(&archive);
+        wait_for(|| !cache.path().join("b").exists());
+        assert!(cache.path().join("a/01.flac").exists());
+    }
+
+    #[test]
+    fn only_archives_out_of_the_cache_are_queued() {
+        let cache = TempDir::new().unwrap();
+        let status = Arc::new(RwLock::new(ArchiveStatus::default()));
+        // No worker picks them up
+        let workers = ArchiveWorkers::start(
+            0,
+            options(),
+            cache.path().to_path_buf(),
+            cache.path(),
+            status,
+        );
+
+        workers.queue(Path::new("/music/song.mp3"));
+        workers.queue(&cache.path().join("nested.zip"));
+        workers.queue(Path::new("/music/album.zip"));
+        workers.queue(Path::new("/music/album.zip"));
+
+        let queued = workers.queue.lock().unwrap().waiting.clone();
+        assert_eq!(queued, HashSet::from([PathBuf::from("/music/album.zip")]));
+    }
+
+    #[test]
+    fn archives_are_extracted_by_one_worker_at_a_time() {
+        let music = TempDir::new().unwrap();
+        let cache = TempDir::new().unwrap();
+        let index = TempDir::new().unwrap();
+        let status = Arc::new(RwLock::new(ArchiveStatus::default()));
+        let workers = ArchiveWorkers::start(
+            2,
+            options(),
+            cache.path().to_path_buf(),
+            index.path(),
+            status,
+        );
+
+        let archive = music.path().join("a.zip");
+        write_zip(&archive);
+        for _ in 0..20 {
+            workers.queue(&archive);
+            thread::sleep(Duration::from_millis(1));
+        }
+        wait_for(|| {
+            let queue = workers.queue.lock().unwrap();
+            queue.waiting.is_empty() && queue.running.is_empty()
+        });
+        assert_eq!(fs::read(cache.path().join("a/01.flac")).unwrap(), b"song");
+        assert!(!cache.path().with_extension("partial").exists());
+
+        // Queued again while it runs, by the same worker
+        let mut queue = workers.queue.lock().unwrap();
+        queue.running.insert(archive.clone(), false);
+        drop(queue);
+        workers.queue(&archive);
+        let queue = workers.queue.lock().unwrap();
+        assert!(queue.waiting.is_empty());
+        assert_eq!(queue.running.get(&archive), Some(&true));
+    }
+}
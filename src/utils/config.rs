@@ -1,40 +1,245 @@
+use super::state::{self, State};
+use crate::columns::{self, ColumnConfig};
+use crate::keymap::{Bindings, Preset};
+use crate::theme::ThemeConfig;
 use dirs::audio_dir;
 use dirs::cache_dir;
 use dirs::config_dir;
+use dirs::data_local_dir;
 use dirs::home_dir;
+use dirs::state_dir;
 use log::LevelFilter;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::VecDeque;
-use std::fs::File;
-use std::path::PathBuf;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use toml_edit::{DocumentMut, Item, Table};
 
-static CONFIG_PATH: &str = "dirplayer/config.json";
+static CONFIG_DIR: &str = "dirplayer";
 static CACHE_PATH: &str = "dirplayer";
+static BOOKMARKS_PATH: &str = "bookmarks.json";
 
-#[derive(Serialize, Deserialize)]
+/// Version of the config files written by this dirplayer.
+pub const CONFIG_VERSION: u64 = 2;
+
+/// Upgrades a config of version `i` to version `i + 1`.
+type Migration = fn(&mut Map<String, Value>);
+
+static MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [migrate_tick_rate, remove_state];
+
+/// Version 1 stores the tick rate as a number of milliseconds.
+fn migrate_tick_rate(config: &mut Map<String, Value>) {
+    let tick_rate = match config.get("tick_rate") {
+        Some(Value::String(tick_rate)) => tick_rate.trim().parse().unwrap_or(500),
+        Some(Value::Number(tick_rate)) => tick_rate.as_u64().unwrap_or(500),
+        _ => 500,
+    };
+    config.insert(String::from("tick_rate"), Value::from(tick_rate));
+}
+
+/// Version 2 leaves what changes while playing to the state file.
+fn remove_state(config: &mut Map<String, Value>) {
+    for key in ["current_file", "working_directory", "play_mode"] {
+        config.remove(key);
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// A standard directory is not defined for this user
+    NoDirectory(&'static str),
+    Io(PathBuf, io::Error),
+    /// The file is not a valid config, moved to `backup` when set
+    Invalid {
+        path: PathBuf,
+        backup: Option<PathBuf>,
+        error: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// The file was written by a newer dirplayer, it is left untouched
+    NewerVersion {
+        path: PathBuf,
+        version: u64,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NoDirectory(name) => write!(f, "Could not find {name} directory"),
+            ConfigError::Io(path, e) => write!(f, "{}: {e}", path.display()),
+            ConfigError::Invalid {
+                path,
+                backup: Some(backup),
+                error,
+            } => write!(
+                f,
+                "Invalid config {}: {error}, moved to {}",
+                path.display(),
+                backup.display()
+            ),
+            ConfigError::Invalid { path, error, .. } => {
+                write!(f, "Invalid config {}: {error}", path.display())
+            }
+            ConfigError::NewerVersion { path, version } => write!(
+                f,
+                "Config {} has version {version}, this dirplayer reads up to {CONFIG_VERSION}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(_, e) => Some(e),
+            ConfigError::Invalid { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
 pub enum PlayMode {
+    #[default]
     Queue,
     Random,
 }
 
-fn get_config_file() -> PathBuf {
-    let mut config_file = config_dir().expect("Could not find home directory.");
-    config_file.push(CONFIG_PATH);
-    config_file
+impl FromStr for PlayMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "queue" => Ok(PlayMode::Queue),
+            "random" => Ok(PlayMode::Random),
+            _ => Err(format!("Unknown play mode {s}, expected queue or random.")),
+        }
+    }
+}
+
+/// Order of the files.
+#[derive(Default, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Sort {
+    /// Most recently created first
+    #[default]
+    Newest,
+    Oldest,
+    Name,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConfigFormat {
+    Json,
+    /// Keeps the comments and the key order of the file when it is saved
+    Toml,
+}
+
+impl ConfigFormat {
+    pub fn of(path: &Path) -> ConfigFormat {
+        match path.extension() {
+            Some(extension) if extension == "toml" => ConfigFormat::Toml,
+            _ => ConfigFormat::Json,
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            ConfigFormat::Json => "config.json",
+            ConfigFormat::Toml => "config.toml",
+        }
+    }
+
+    fn other(self) -> ConfigFormat {
+        match self {
+            ConfigFormat::Json => ConfigFormat::Toml,
+            ConfigFormat::Toml => ConfigFormat::Json,
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(ConfigFormat::Json),
+            "toml" => Ok(ConfigFormat::Toml),
+            _ => Err(format!("Unknown config format {s}, expected json or toml.")),
+        }
+    }
 }
 
-pub fn get_cache_dir() -> PathBuf {
-    let mut cache_dir = cache_dir().expect("Could not find cache directory.");
+fn get_config_file_in(format: ConfigFormat) -> Result<PathBuf, ConfigError> {
+    let mut config_file = config_dir().ok_or(ConfigError::NoDirectory("config"))?;
+    config_file.push(CONFIG_DIR);
+    config_file.push(format.file_name());
+    Ok(config_file)
+}
+
+/// config.toml when present, config.json otherwise.
+pub fn get_config_file() -> Result<PathBuf, ConfigError> {
+    let toml_file = get_config_file_in(ConfigFormat::Toml)?;
+    if toml_file.is_file() {
+        Ok(toml_file)
+    } else {
+        get_config_file_in(ConfigFormat::Json)
+    }
+}
+
+pub fn get_cache_dir() -> Result<PathBuf, ConfigError> {
+    let mut cache_dir = cache_dir().ok_or(ConfigError::NoDirectory("cache"))?;
     cache_dir.push(CACHE_PATH);
-    cache_dir
+    Ok(cache_dir)
+}
+
+/// Where files written while playing are kept. Not in the cache directory,
+/// which is watched as a working directory: writing there would scan every
+/// working directory again.
+pub fn get_data_dir() -> Result<PathBuf, ConfigError> {
+    let mut data_dir = state_dir()
+        .or_else(data_local_dir)
+        .ok_or(ConfigError::NoDirectory("data"))?;
+    data_dir.push(CACHE_PATH);
+    Ok(data_dir)
+}
+
+pub fn get_bookmarks_file() -> Result<PathBuf, ConfigError> {
+    let bookmarks_file = get_data_dir()?.join(BOOKMARKS_PATH);
+    if let Ok(cache_dir) = get_cache_dir() {
+        super::move_legacy_file(&cache_dir.join(BOOKMARKS_PATH), &bookmarks_file);
+    }
+    Ok(bookmarks_file)
 }
 
-pub fn get_audio_dir() -> PathBuf {
-    audio_dir().expect("Could not find audio directory.")
+pub fn get_audio_dir() -> Result<PathBuf, ConfigError> {
+    audio_dir().ok_or(ConfigError::NoDirectory("audio"))
 }
 
-pub fn get_home_dir() -> PathBuf {
-    home_dir().expect("Could not find home directory.")
+pub fn get_home_dir() -> Result<PathBuf, ConfigError> {
+    home_dir().ok_or(ConfigError::NoDirectory("home"))
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Backend {
+    /// Rodio, and mpv for opus files.
+    #[default]
+    Native,
+    /// Plays nothing and simulates time, for tests.
+    Null,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum ArchiveMode {
+    /// Archives are extracted to the cache directory.
+    #[default]
+    Extract,
+    /// Zip archive members are listed and played in place, others are still extracted.
+    Virtual,
 }
 
 #[derive(Default, Serialize, Deserialize, Clone, PartialEq)]
@@ -51,101 +256,510 @@ pub struct WorkingPath {
     pub status: Status,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
+    // Configs without a version predate versioning
+    #[serde(default)]
+    pub version: u64,
     pub extensions: Vec<String>,
     pub extensions_archives: Vec<String>,
-    pub tick_rate: String,
+    // Milliseconds
+    pub tick_rate: u64,
     pub working_directories: VecDeque<WorkingPath>,
     // pub working_directories_line_index: HashMap<String, i32>,
-    // Directory for current completions
-    pub working_directory: String,
-    pub play_mode: PlayMode,
     pub log_level: LevelFilter,
-    pub current_file: String,
+    #[serde(default)]
+    pub backend: Backend,
+    // Default device when None
+    #[serde(default)]
+    pub output_device: Option<String>,
+    // Playback speed by directory
+    #[serde(default)]
+    pub speeds: HashMap<String, f32>,
+    #[serde(default)]
+    pub archive_mode: ArchiveMode,
+    // Size cap of extracted archives in megabytes, unlimited when None
+    #[serde(default)]
+    pub archive_cache_max_mb: Option<u64>,
+    // Passwords of encrypted archives, by path or file name
+    #[serde(default)]
+    pub archive_passwords: HashMap<String, String>,
+    // Archives found in archives are extracted up to this depth
+    #[serde(default = "default_nested_archive_depth")]
+    pub nested_archive_depth: usize,
+    #[serde(default)]
+    pub keymap_preset: Preset,
+    // Keys of actions by context, replacing the keys of the preset
+    #[serde(default)]
+    pub keybindings: Bindings,
+    // Built-in theme, dark, light or high_contrast, or one of themes
+    #[serde(default = "default_theme")]
+    pub theme: String,
+    #[serde(default)]
+    pub themes: HashMap<String, ThemeConfig>,
+    // Of the created and modified columns, see chrono's strftime
+    #[serde(default = "columns::default_date_format")]
+    pub date_format: String,
+    // Of the Files table, from left to right
+    #[serde(default = "columns::default_columns")]
+    pub columns: Vec<ColumnConfig>,
+}
+
+fn default_nested_archive_depth() -> usize {
+    2
+}
+
+fn default_theme() -> String {
+    String::from("dark")
 }
 
 impl Default for Config {
     fn default() -> Self {
+        let audio_dir = get_audio_dir()
+            .or_else(|_| get_home_dir())
+            .unwrap_or_default();
+        let mut working_directories = VecDeque::from([WorkingPath {
+            path: audio_dir.to_string_lossy().to_string(),
+            status: Status::Active,
+        }]);
+        if let Ok(cache_dir) = get_cache_dir() {
+            working_directories.push_back(WorkingPath {
+                path: cache_dir.to_string_lossy().to_string(),
+                status: Status::Cache,
+            });
+        }
+
         Config {
+            version: CONFIG_VERSION,
             extensions: vec!["mp3", "mp4", "avi", "ogg", "m4a", "opus", "flac"]
                 .into_iter()
                 .map(String::from)
                 .collect(),
-            extensions_archives: vec!["zip"].into_iter().map(String::from).collect(),
-            tick_rate: String::from("500"),
-            working_directories: VecDeque::from([
-                WorkingPath {
-                    path: String::from(
-                        get_audio_dir()
-                            .to_str()
-                            .unwrap_or(get_home_dir().to_str().unwrap()),
-                    ),
-                    status: Status::Active,
-                },
-                WorkingPath {
-                    path: String::from(
-                        get_cache_dir()
-                            .to_str()
-                            .expect("Could not find cache directory."),
-                    ),
-                    status: Status::Cache,
-                },
-            ]),
-            working_directory: String::from(PathBuf::default().to_str().unwrap()),
-            play_mode: PlayMode::Queue,
+            // Compressed tarballs only, not every compressed file
+            extensions_archives: vec![
+                "zip", "tar", "tar.gz", "tgz", "tar.xz", "txz", "tar.zst", "tzst", "7z",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            tick_rate: 500,
+            working_directories,
             log_level: LevelFilter::Info,
-            current_file: "".to_string(),
+            backend: Backend::default(),
+            output_device: None,
+            speeds: HashMap::new(),
+            archive_mode: ArchiveMode::default(),
+            archive_cache_max_mb: None,
+            archive_passwords: HashMap::new(),
+            nested_archive_depth: default_nested_archive_depth(),
+            keymap_preset: Preset::default(),
+            keybindings: Bindings::new(),
+            theme: default_theme(),
+            themes: HashMap::new(),
+            date_format: columns::default_date_format(),
+            columns: columns::default_columns(),
         }
     }
 }
 
-pub fn set_defaut_config() -> Config {
-    let config_file = get_config_file();
-    let config = Config::default();
-    let path_to_config = config_file.parent().unwrap();
-    std::fs::create_dir_all(path_to_config).expect("Could not crate path to config file.");
-    let file = File::create(config_file).expect("Could not create config file.");
-    serde_json::to_writer(file, &config).expect("Could not save to config file.");
-    config
+/// Read the config at `path`, upgraded to the current version. A missing file
+/// gives the default config.
+pub fn read_config(path: &Path) -> Result<Config, ConfigError> {
+    read_versioned_config(path).map(|loaded| loaded.config)
 }
 
-pub fn get_set_config() -> Config {
-    let config_file = get_config_file();
+struct Loaded {
+    config: Config,
+    // None when there is no file
+    version: Option<u64>,
+    // Saved in the config before version 2
+    state: Option<State>,
+}
 
-    if config_file.is_file() {
-        let file = File::open(config_file).expect("Could not find config file.");
-        match serde_json::from_reader(file) {
-            Ok(config) => config,
-            Err(_) => set_defaut_config(),
+fn read_versioned_config(path: &Path) -> Result<Loaded, ConfigError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(Loaded {
+                config: Config::default(),
+                version: None,
+                state: None,
+            })
         }
-    } else {
-        set_defaut_config()
+        Err(e) => return Err(ConfigError::Io(path.to_path_buf(), e)),
+    };
+    let invalid = |error| ConfigError::Invalid {
+        path: path.to_path_buf(),
+        backup: None,
+        error,
+    };
+    // Migrations work on json values, whatever the format
+    let parsed = match ConfigFormat::of(path) {
+        ConfigFormat::Json => serde_json::from_str(&contents).map_err(Into::into),
+        ConfigFormat::Toml => toml::from_str(&contents).map_err(Into::into),
+    };
+    let mut value: Map<String, Value> = parsed.map_err(invalid)?;
+
+    let version = value.get("version").and_then(Value::as_u64).unwrap_or(0);
+    if version > CONFIG_VERSION {
+        return Err(ConfigError::NewerVersion {
+            path: path.to_path_buf(),
+            version,
+        });
     }
+    let state = (version < 2)
+        .then(|| serde_json::from_value(Value::Object(value.clone())).ok())
+        .flatten();
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut value);
+    }
+    value.insert(String::from("version"), Value::from(CONFIG_VERSION));
+
+    let config = serde_json::from_value(Value::Object(value)).map_err(|e| invalid(e.into()))?;
+    Ok(Loaded {
+        config,
+        version: Some(version),
+        state,
+    })
+}
+
+pub fn write_config(path: &Path, config: &Config) -> Result<(), ConfigError> {
+    let io_error = |e| ConfigError::Io(path.to_path_buf(), e);
+    let contents = match ConfigFormat::of(path) {
+        ConfigFormat::Json => serde_json::to_string_pretty(config).map_err(io::Error::from),
+        ConfigFormat::Toml => format_toml(path, config),
+    };
+    super::write_atomic(path, contents.map_err(io_error)?.as_bytes()).map_err(io_error)
+}
+
+/// `config` in the document of `path`, so that its comments, formatting and key
+/// order survive.
+fn format_toml(path: &Path, config: &Config) -> io::Result<String> {
+    let new: DocumentMut = toml::to_string_pretty(config)
+        .map_err(io::Error::other)?
+        .parse()
+        .map_err(io::Error::other)?;
+    let document = fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| contents.parse::<DocumentMut>().ok());
+    match document {
+        Some(mut document) => {
+            merge_table(document.as_table_mut(), new.as_table());
+            Ok(document.to_string())
+        }
+        None => Ok(new.to_string()),
+    }
+}
+
+fn merge_table(old: &mut Table, new: &Table) {
+    old.retain(|key, _| new.contains_key(key));
+    for (key, new_item) in new.iter() {
+        match old.get_mut(key) {
+            Some(old_item) => merge_item(old_item, new_item),
+            None => {
+                old.insert(key, new_item.clone());
+            }
+        }
+    }
+}
+
+fn merge_item(old: &mut Item, new: &Item) {
+    match (old, new) {
+        (Item::Table(old), Item::Table(new)) => merge_table(old, new),
+        (Item::ArrayOfTables(old), Item::ArrayOfTables(new)) => {
+            // Tables are matched by position
+            while old.len() > new.len() {
+                old.remove(old.len() - 1);
+            }
+            for (i, new_table) in new.iter().enumerate() {
+                match old.get_mut(i) {
+                    Some(old_table) => merge_table(old_table, new_table),
+                    None => old.push(new_table.clone()),
+                }
+            }
+        }
+        (Item::Value(old), Item::Value(new)) => {
+            if !same_value(old, new) {
+                let decor = old.decor().clone();
+                *old = new.clone();
+                *old.decor_mut() = decor;
+            }
+        }
+        (old, new) => *old = new.clone(),
+    }
+}
+
+/// Whether `a` and `b` hold the same data, however they are written.
+fn same_value(a: &toml_edit::Value, b: &toml_edit::Value) -> bool {
+    use toml_edit::Value::*;
+    match (a, b) {
+        (String(a), String(b)) => a.value() == b.value(),
+        (Integer(a), Integer(b)) => a.value() == b.value(),
+        (Float(a), Float(b)) => a.value() == b.value(),
+        (Boolean(a), Boolean(b)) => a.value() == b.value(),
+        (Datetime(a), Datetime(b)) => a.value() == b.value(),
+        (Array(a), Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| same_value(a, b))
+        }
+        (InlineTable(a), InlineTable(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| same_value(a, b)))
+        }
+        _ => false,
+    }
+}
+
+/// Read the config at `path` on startup, writing it back when it is missing or
+/// outdated. Invalid files are moved aside, and reported, before defaults are
+/// written; outdated ones are copied aside before they are upgraded, and the
+/// state they held moves to `state_path`.
+pub fn load_config(path: &Path, state_path: &Path) -> Result<Config, ConfigError> {
+    match read_versioned_config(path) {
+        Ok(Loaded {
+            config,
+            version,
+            state,
+        }) => {
+            if let Some(state) = state.filter(|_| !state_path.exists()) {
+                state::write_state(state_path, &state)?;
+            }
+            if version != Some(CONFIG_VERSION) {
+                if let Some(version) = version {
+                    let backup = backup_path(path, &format!("v{version}"));
+                    fs::copy(path, &backup).map_err(|e| ConfigError::Io(backup, e))?;
+                }
+                write_config(path, &config)?;
+            }
+            Ok(config)
+        }
+        Err(ConfigError::Invalid { error, .. }) => {
+            let backup = backup_path(
+                path,
+                &format!("invalid-{}", chrono::Local::now().format("%Y%m%d-%H%M%S")),
+            );
+            fs::rename(path, &backup).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+            write_config(path, &Config::default())?;
+            Err(ConfigError::Invalid {
+                path: path.to_path_buf(),
+                backup: Some(backup),
+                error,
+            })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn backup_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".{suffix}"));
+    PathBuf::from(backup)
+}
+
+pub fn get_set_config() -> Result<Config, ConfigError> {
+    load_config(&get_config_file()?, &state::get_state_file()?)
+}
+
+pub fn get_config() -> Result<Config, ConfigError> {
+    read_config(&get_config_file()?)
+}
+
+pub fn update_config(config: &Config) -> Result<(), ConfigError> {
+    write_config(&get_config_file()?, config)
 }
 
-pub fn get_config() -> Config {
-    let config_file = get_config_file();
-    let file = File::open(config_file).expect("Could not find config file.");
-    serde_json::from_reader(file).expect("Could not parse config file as json.")
+/// Convert the config file to `format`, the other format by default, and
+/// return the new file. The previous file is kept with a .bak extension.
+pub fn migrate_config(format: Option<ConfigFormat>) -> Result<PathBuf, ConfigError> {
+    let from = get_config_file()?;
+    let to = get_config_file_in(format.unwrap_or(ConfigFormat::of(&from).other()))?;
+    if from != to {
+        migrate(&from, &to)?;
+    }
+    Ok(to)
 }
 
-pub fn update_config(config: &Config) {
-    let config_file = get_config_file();
-    let path_to_config = config_file.parent().unwrap();
-    std::fs::create_dir_all(path_to_config).expect("Could not crate path to config file.");
-    let file = File::create(config_file).expect("Could not create config file.");
-    serde_json::to_writer_pretty(file, &config).expect("Could not save to config file.");
+fn migrate(from: &Path, to: &Path) -> Result<(), ConfigError> {
+    let config = read_config(from)?;
+    // Starts from scratch, not from a stale file
+    if to.exists() {
+        let backup = backup_path(to, "bak");
+        fs::rename(to, backup).map_err(|e| ConfigError::Io(to.to_path_buf(), e))?;
+    }
+    write_config(to, &config)?;
+    if from.exists() {
+        let backup = backup_path(from, "bak");
+        fs::rename(from, backup).map_err(|e| ConfigError::Io(from.to_path_buf(), e))?;
+    }
+    Ok(())
 }
 
-pub fn update_working_directory(working_directory: &str) {
-    let config_file = get_config_file();
-    let path_to_config = &config_file.parent().unwrap();
-    let file = File::open(&config_file).expect("Could not find config file.");
-    let mut config: Config =
-        serde_json::from_reader(file).expect("Could not parse config file as json.");
-    config.working_directory = String::from(working_directory);
-    std::fs::create_dir_all(path_to_config).expect("Could not crate path to config file.");
-    let file = File::create(config_file).expect("Could not create config file.");
-    serde_json::to_writer_pretty(file, &config).expect("Could not save to config file.");
+/// The config, or the default one when it cannot be read, with the error logged.
+pub fn get_config_or_default() -> Config {
+    get_config().unwrap_or_else(|e| {
+        log::error!("{e}");
+        Config::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+    use serde_json::json;
+    use std::fs::File;
+    use tempfile::TempDir;
+
+    fn write(path: &Path, value: &str) {
+        fs::write(path, value).unwrap();
+    }
+
+    fn unversioned_config() -> Value {
+        let mut config = serde_json::to_value(Config::default()).unwrap();
+        let config_map = config.as_object_mut().unwrap();
+        config_map.remove("version");
+        config_map.insert(String::from("tick_rate"), json!("250"));
+        config_map.insert(String::from("current_file"), json!("/music/a.mp3"));
+        config_map.insert(String::from("working_directory"), json!("/music"));
+        config_map.insert(String::from("play_mode"), json!("Random"));
+        config
+    }
+
+    #[test]
+    fn missing_config_is_written_with_defaults() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("dirplayer/config.json");
+
+        let config = load_config(&path, &dir.path().join("state.json")).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(read_config(&path).unwrap().tick_rate, 500);
+    }
+
+    #[test]
+    fn unversioned_config_is_migrated_and_kept() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        write(&path, &unversioned_config().to_string());
+
+        let state_path = dir.path().join("state.json");
+        let config = load_config(&path, &state_path).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.tick_rate, 250);
+
+        let saved: Value = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+        assert_eq!(saved["version"], json!(CONFIG_VERSION));
+        assert_eq!(saved["tick_rate"], json!(250));
+        assert_eq!(saved.get("current_file"), None);
+        let state = state::read_state(&state_path).unwrap().unwrap();
+        assert_eq!(state.current_file, "/music/a.mp3");
+        assert_eq!(state.working_directory, "/music");
+        assert_eq!(state.play_mode, PlayMode::Random);
+        let backup = dir.path().join("config.json.v0");
+        assert_eq!(
+            fs::read_to_string(backup).unwrap(),
+            unversioned_config().to_string()
+        );
+    }
+
+    #[test]
+    fn invalid_config_is_moved_aside_and_reported() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        write(&path, "{\"extensions\": [");
+
+        let backup = match load_config(&path, &dir.path().join("state.json")) {
+            Err(ConfigError::Invalid {
+                backup: Some(backup),
+                ..
+            }) => backup,
+            _ => panic!("expected an invalid config"),
+        };
+        assert_eq!(fs::read_to_string(backup).unwrap(), "{\"extensions\": [");
+        assert_eq!(read_config(&path).unwrap().version, CONFIG_VERSION);
+    }
+
+    #[test]
+    fn unknown_names_are_kept_for_their_part_to_report() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        let mut config = serde_json::to_value(Config::default()).unwrap();
+        config["keybindings"] = json!({ "files": { "jump": ["j"] }, "playlist": {} });
+        config["themes"] = json!({ "mine": { "base": "neon", "colors": { "border": "red" } } });
+        config["columns"] = json!([{ "kind": "titel" }]);
+        write(&path, &config.to_string());
+
+        let config = load_config(&path, &dir.path().join("state.json")).unwrap();
+        assert_eq!(config.keybindings["files"]["jump"], vec!["j"]);
+        assert_eq!(config.themes["mine"].colors["border"], "red");
+        assert_eq!(config.columns[0].kind, "titel");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn newer_config_is_left_untouched() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+        let newer = json!({ "version": CONFIG_VERSION + 1 }).to_string();
+        write(&path, &newer);
+
+        assert!(matches!(
+            load_config(&path, &dir.path().join("state.json")),
+            Err(ConfigError::NewerVersion { version, .. }) if version == CONFIG_VERSION + 1
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), newer);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn toml_config_keeps_comments_and_key_order() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        let config = Config {
+            tick_rate: 250,
+            ..Config::default()
+        };
+        let rest = toml::to_string_pretty(&config)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with("tick_rate"))
+            .join("\n");
+        write(
+            &path,
+            &format!("# Tweaked by hand\ntick_rate = 250 # milliseconds\n{rest}"),
+        );
+
+        write_config(
+            &path,
+            &Config {
+                log_level: LevelFilter::Debug,
+                ..config
+            },
+        )
+        .unwrap();
+
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.starts_with("# Tweaked by hand\ntick_rate = 250 # milliseconds\n"));
+        assert!(saved.contains("log_level = \"DEBUG\""));
+        let config = read_config(&path).unwrap();
+        assert_eq!(config.tick_rate, 250);
+        assert_eq!(config.log_level, LevelFilter::Debug);
+    }
+
+    #[test]
+    fn json_config_is_migrated_to_toml() {
+        let dir = TempDir::new().unwrap();
+        let json = dir.path().join("config.json");
+        let toml = dir.path().join("config.toml");
+        write(&json, &unversioned_config().to_string());
+
+        migrate(&json, &toml).unwrap();
+        assert!(!json.exists());
+        assert!(dir.path().join("config.json.bak").exists());
+        let config = read_config(&toml).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.tick_rate, 250);
+
+        migrate(&toml, &json).unwrap();
+        assert_eq!(read_config(&json).unwrap().tick_rate, 250);
+    }
 }
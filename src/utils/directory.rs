@@ -1,17 +1,33 @@
+use super::archive::{
+    self, ArchiveOptions, ArchiveStatus, Extractor, PasswordRequest, PasswordRequired, Progress,
+};
+use super::archive_cache::{self, ArchiveCache, Provenance};
+use anyhow::Context;
 use itertools::Itertools;
 use std::fs;
-use std::io;
+use std::io::BufReader;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
 use walkdir::{DirEntry, WalkDir};
 
+/// Suffix match, so that extensions such as "tar.gz" work
+pub fn has_extension(path: &Path, extentions: &[String]) -> bool {
+    let Some(file_name) = path.file_name() else {
+        return false;
+    };
+    let file_name = file_name.to_string_lossy();
+    extentions
+        .iter()
+        .any(|extension| file_name.ends_with(&format!(".{extension}")))
+}
+
 pub fn get_direntries(path: &Path, extentions: &[String]) -> Vec<DirEntry> {
     WalkDir::new(path)
         .into_iter()
         .filter_map(move |e| {
             let dir_entry = e.as_ref().ok()?;
-            let extension_archives = dir_entry.path().extension()?.to_string_lossy().to_string();
-            if dir_entry.file_type().is_file() && extentions.contains(&extension_archives) {
+            if dir_entry.file_type().is_file() && has_extension(dir_entry.path(), extentions) {
                 e.ok()
             } else {
                 None
@@ -20,48 +36,169 @@ pub fn get_direntries(path: &Path, extentions: &[String]) -> Vec<DirEntry> {
         .collect_vec()
 }
 
-/// Uncompress archives to cache directory
-pub fn process_archives(paths: &[String], extensions_archives: &Vec<String>, cache_dir: &PathBuf) {
-    let archive_lines = paths
+/// Archives of `paths`, outside of the cache directory
+pub fn find_archives(
+    paths: &[String],
+    extensions_archives: &[String],
+    cache_dir: &Path,
+) -> Vec<PathBuf> {
+    paths
         .iter()
         // Avoid entirely scanning cache directory
-        .filter(|direntry| **direntry != cache_dir.to_string_lossy())
-        .flat_map(|direntry| get_direntries(&PathBuf::from(direntry), extensions_archives))
-        .collect_vec();
+        .filter(|direntry| Path::new(direntry) != cache_dir)
+        .flat_map(|direntry| get_direntries(Path::new(direntry), extensions_archives))
+        .map(|direntry| direntry.into_path())
+        .collect_vec()
+}
 
-    archive_lines
-        .iter()
-        .filter_map(|archive| {
-            let file_name = PathBuf::from(archive.file_name());
-            let archive_name = file_name.file_stem()?;
-            let mut archive_directory = cache_dir.clone();
-            archive_directory.push(archive_name);
-            if archive_directory.as_path().exists() {
-                return None;
-            }
+/// Uncompress `archive` to cache directory, unless it did not change since last time
+pub fn process_archive(
+    archive: &Path,
+    cache_dir: &Path,
+    cache: &Mutex<ArchiveCache>,
+    options: &ArchiveOptions,
+    status: &Arc<RwLock<ArchiveStatus>>,
+) {
+    // Removed archives are handled by clean_archive_cache
+    if !archive.is_file() {
+        return;
+    }
+    let Some(extractor) = archive::find_extractor(archive) else {
+        log::debug!("Unknown archive format : {:?}", archive);
+        return;
+    };
+    let Some(archive_name) = archive::archive_name(extractor.as_ref(), archive) else {
+        return;
+    };
 
-            let archive_file = fs::File::open(archive.path()).ok()?;
-            let mut archive = zip::ZipArchive::new(archive_file).ok()?;
+    // Hashing can take a while, do not hold the cache meanwhile
+    let previous = cache.lock().unwrap().provenance(&archive_name);
+    let provenance = match Provenance::of(archive, previous.as_ref()) {
+        Ok(provenance) => provenance,
+        Err(e) => {
+            log::error!("Could not read {:?}: {e}", archive);
+            return;
+        }
+    };
+    if !cache
+        .lock()
+        .unwrap()
+        .outdated(&archive_name, &provenance, options.max_size)
+    {
+        return;
+    }
 
-            for i in 0..archive.len() {
-                let mut file = archive.by_index(i).ok()?;
-                let outpath_name = file.enclosed_name()?.to_owned();
-                let mut outpath_dir = archive_directory.clone();
-                log::debug!("Building archive : {:?}", outpath_dir);
-                outpath_dir.push(&outpath_name);
-                if file.is_dir() {
-                    fs::create_dir_all(&outpath_dir).unwrap();
-                } else {
-                    if let Some(p) = outpath_dir.parent() {
-                        if !p.exists() {
-                            fs::create_dir_all(p).unwrap();
-                        }
-                    }
-                    let mut outfile = fs::File::create(&outpath_dir).unwrap();
-                    io::copy(&mut file, &mut outfile).unwrap();
-                }
+    let archive_directory = cache_dir.join(&archive_name);
+    log::debug!("Building archive : {:?}", archive_directory);
+    let result = extract(
+        extractor.as_ref(),
+        archive,
+        &archive_directory,
+        options,
+        status,
+    );
+    let mut cache = cache.lock().unwrap();
+    let mut status = status.write().unwrap();
+    status
+        .password_requests
+        .retain(|request| request.archive != archive);
+    match result {
+        Ok(()) => cache.insert(&archive_name, provenance),
+        Err(e) => {
+            log::error!("Could not extract {:?}: {e}", archive);
+            if let Some(PasswordRequired { wrong }) = e.downcast_ref() {
+                status.password_requests.push(PasswordRequest {
+                    archive: archive.to_path_buf(),
+                    wrong: *wrong,
+                });
             }
-            Some(())
-        })
-        .collect_vec();
+            cache.insert_failure(&archive_name, provenance, format!("{e:#}"));
+        }
+    }
+    status.failed = cache.failures();
+}
+
+/// Remove what is left of removed archives and keep the cache under `max_size` bytes
+pub fn clean_archive_cache(
+    cache: &Mutex<ArchiveCache>,
+    max_size: Option<u64>,
+    status: &Arc<RwLock<ArchiveStatus>>,
+) {
+    let mut cache = cache.lock().unwrap();
+    cache.remove_orphans();
+    if let Some(max_size) = max_size {
+        cache.evict(max_size);
+    }
+    cache.save();
+    status.write().unwrap().failed = cache.failures();
+}
+
+/// Extract next to the cache directory, which may be watched, and only move the
+/// result in place once complete.
+fn extract(
+    extractor: &dyn Extractor,
+    archive: &Path,
+    destination: &Path,
+    options: &ArchiveOptions,
+    status: &Arc<RwLock<ArchiveStatus>>,
+) -> anyhow::Result<()> {
+    let cache_dir = destination.parent().context("No cache directory")?;
+    let partial_dir = cache_dir.with_extension("partial");
+    // Apart from other extractions to the same name, removed unless moved in place.
+    // Another worker may remove the parent in between, once empty.
+    let staging = || {
+        fs::create_dir_all(&partial_dir)?;
+        tempfile::TempDir::new_in(&partial_dir)
+    };
+    let partial = staging().or_else(|_| staging())?;
+
+    let file = fs::File::open(archive)?;
+    let size = file.metadata()?.len();
+    let name = archive.file_name().unwrap_or_default().to_string_lossy();
+    let reader = Progress::new(BufReader::new(file), &name, size, status.clone());
+    let password = options.password(archive);
+    let result = extractor
+        .extract(Box::new(reader), partial.path(), password.as_deref())
+        .and_then(|()| extract_nested(partial.path(), options, password.as_deref(), 1))
+        .and_then(|()| {
+            archive_cache::remove_folder(destination);
+            Ok(fs::rename(partial.path(), destination)?)
+        });
+    drop(partial);
+    // Only removed once empty
+    let _ = fs::remove_dir(partial_dir);
+    result
+}
+
+/// Replace the archives of `directory` by their content, up to `options.max_depth` levels.
+/// Nested archives without a password of their own use the one of their parent.
+fn extract_nested(
+    directory: &Path,
+    options: &ArchiveOptions,
+    password: Option<&str>,
+    depth: usize,
+) -> anyhow::Result<()> {
+    if depth > options.max_depth {
+        return Ok(());
+    }
+    for nested in get_direntries(directory, &options.extensions_archives) {
+        let nested = nested.path();
+        let Some(extractor) = archive::find_extractor(nested) else {
+            continue;
+        };
+        let Some(name) = archive::archive_name(extractor.as_ref(), nested) else {
+            continue;
+        };
+        let destination = nested.with_file_name(name);
+        let nested_password = options.password(nested);
+        let password = nested_password.as_deref().or(password);
+        let file = fs::File::open(nested)?;
+        let file_name = nested.file_name().unwrap_or_default().to_string_lossy();
+        extractor
+            .extract(Box::new(BufReader::new(file)), &destination, password)
+            .with_context(|| format!("In {file_name}"))?;
+        fs::remove_file(nested)?;
+        extract_nested(&destination, options, password, depth + 1)?;
+    }
+    Ok(())
 }
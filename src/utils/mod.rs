@@ -1,9 +1,19 @@
 use std::cmp;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
 
+pub mod archive;
+pub mod archive_cache;
+pub mod archive_workers;
 pub mod config;
 pub mod directory;
 pub mod macros;
+pub mod settings;
+pub mod state;
 pub mod style;
+pub mod tags;
 
 struct RangeCkecker {
     range: (isize, isize),
@@ -63,3 +73,50 @@ pub fn get_borders(
 
     (checked_high, lines_index_checked, checked_low)
 }
+
+/// Format as minutes:seconds, or hours:minutes:seconds for long files.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// Move `from` to `to` unless there is already a file there, for files that
+/// older versions kept elsewhere.
+pub fn move_legacy_file(from: &Path, to: &Path) {
+    if !from.exists() || to.exists() {
+        return;
+    }
+    let moved = match to.parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+    .and_then(|_| fs::rename(from, to));
+    match moved {
+        Ok(()) => log::info!("Moved {:?} to {:?}", from, to),
+        Err(e) => log::error!("Could not move {:?} to {:?}: {e}", from, to),
+    }
+}
+
+/// Write through a temporary file renamed over `path`, so that readers never
+/// see a partial file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(parent)?;
+    // Named apart from the one of any other writer
+    let mut temporary = tempfile::NamedTempFile::new_in(parent)?;
+    temporary.write_all(contents)?;
+    temporary.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
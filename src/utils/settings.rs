@@ -0,0 +1,285 @@
+use super::config::{self, Config};
+use super::state::{self, State};
+use crate::constants::SAVE_DELAY;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+// The config and the state are read once on startup, and shared by App, Files
+// and Directories. Changes are saved once they settle, and when the last clone
+// is dropped. The config is read again when it is edited while playing, keeping
+// the fields changed here since the last save.
+
+/// Clones share the same config and state.
+#[derive(Clone)]
+pub struct Settings {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    config: Config,
+    // As last written or read, to tell the fields changed here
+    config_saved: Config,
+    state: State,
+    // Nothing is saved when None
+    config_file: Option<PathBuf>,
+    state_file: Option<PathBuf>,
+    // Last unsaved change
+    config_changed: Option<Instant>,
+    state_changed: Option<Instant>,
+    // Modification time of the config file last written or read here
+    config_modified: Option<SystemTime>,
+}
+
+impl Settings {
+    /// Saved to the config and state files.
+    pub fn load() -> Settings {
+        let log_error = |e: config::ConfigError| log::error!("{e}");
+        Settings::new(
+            config::get_config_or_default(),
+            state::get_state(),
+            config::get_config_file().map_err(log_error).ok(),
+            state::get_state_file().map_err(log_error).ok(),
+        )
+    }
+
+    /// Saved nowhere.
+    pub fn in_memory(config: Config, state: State) -> Settings {
+        Settings::new(config, state, None, None)
+    }
+
+    fn new(
+        config: Config,
+        state: State,
+        config_file: Option<PathBuf>,
+        state_file: Option<PathBuf>,
+    ) -> Settings {
+        let config_modified = config_file.as_deref().and_then(modified);
+        Settings {
+            inner: Arc::new(Mutex::new(Inner {
+                config_saved: config.clone(),
+                config,
+                state,
+                config_file,
+                state_file,
+                config_changed: None,
+                state_changed: None,
+                config_modified,
+            })),
+        }
+    }
+
+    pub fn config_file(&self) -> Option<PathBuf> {
+        self.inner.lock().unwrap().config_file.clone()
+    }
+
+    pub fn config(&self) -> Config {
+        self.inner.lock().unwrap().config.clone()
+    }
+
+    pub fn state(&self) -> State {
+        self.inner.lock().unwrap().state.clone()
+    }
+
+    pub fn modify_config(&self, modify: impl FnOnce(&mut Config)) {
+        let mut inner = self.inner.lock().unwrap();
+        modify(&mut inner.config);
+        inner.config_changed = Some(Instant::now());
+    }
+
+    pub fn modify_state(&self, modify: impl FnOnce(&mut State)) {
+        let mut inner = self.inner.lock().unwrap();
+        modify(&mut inner.state);
+        inner.state_changed = Some(Instant::now());
+    }
+
+    /// Read the config file again, when it was changed by someone else, and
+    /// return whether it was. The config is kept when the file is invalid, and
+    /// the fields changed here and not saved yet are kept, to be saved.
+    pub fn reload(&self) -> Result<bool, config::ConfigError> {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(path) = inner.config_file.clone() else {
+            return Ok(false);
+        };
+        // Removed while it is replaced, read once it is back
+        let Some(modified) = modified(&path) else {
+            return Ok(false);
+        };
+        if inner.config_modified == Some(modified) {
+            return Ok(false);
+        }
+        let edited = config::read_config(&path)?;
+        inner.config = match inner.config_changed {
+            Some(_) => keep_changes(&inner.config_saved, &inner.config, edited.clone()),
+            None => edited.clone(),
+        };
+        inner.config_saved = edited;
+        inner.config_modified = Some(modified);
+        Ok(true)
+    }
+
+    /// Save what has not changed for a while.
+    pub fn save_settled(&self) {
+        self.inner
+            .lock()
+            .unwrap()
+            .save(|changed| changed.elapsed() >= SAVE_DELAY);
+    }
+}
+
+impl Inner {
+    fn save(&mut self, due: impl Fn(Instant) -> bool) {
+        if self.config_changed.is_some_and(&due) {
+            self.config_changed = None;
+            if let Some(path) = &self.config_file {
+                if let Err(e) = config::write_config(path, &self.config) {
+                    log::error!("Could not save config: {e}");
+                }
+                self.config_saved = self.config.clone();
+                self.config_modified = modified(path);
+            }
+        }
+        if self.state_changed.is_some_and(&due) {
+            self.state_changed = None;
+            if let Some(path) = &self.state_file {
+                if let Err(e) = state::write_state(path, &self.state) {
+                    log::error!("Could not save state: {e}");
+                }
+            }
+        }
+    }
+}
+
+/// `edited` with the fields changed from `saved` to `current`.
+fn keep_changes(saved: &Config, current: &Config, edited: Config) -> Config {
+    let values = (
+        serde_json::to_value(saved),
+        serde_json::to_value(current),
+        serde_json::to_value(&edited),
+    );
+    let (Ok(Value::Object(saved)), Ok(Value::Object(current)), Ok(Value::Object(mut merged))) =
+        values
+    else {
+        return edited;
+    };
+    for (key, value) in current {
+        if saved.get(&key) != Some(&value) {
+            log::info!("Keeping {key}, changed while the config file was edited");
+            merged.insert(key, value);
+        }
+    }
+    serde_json::from_value(Value::Object(merged)).unwrap_or(edited)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.save(|_| true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::config::PlayMode;
+    use std::thread;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn settings(dir: &TempDir) -> Settings {
+        Settings::new(
+            Config::default(),
+            State::default(),
+            Some(dir.path().join("config.json")),
+            Some(dir.path().join("state.json")),
+        )
+    }
+
+    #[test]
+    fn changes_are_saved_once_settled() {
+        let dir = TempDir::new().unwrap();
+        let settings = settings(&dir);
+        let state_file = dir.path().join("state.json");
+
+        settings.modify_state(|state| state.play_mode = PlayMode::Random);
+        settings.save_settled();
+        assert!(!state_file.exists());
+        assert!(!dir.path().join("config.json").exists());
+
+        thread::sleep(SAVE_DELAY);
+        settings.save_settled();
+        let saved = state::read_state(&state_file).unwrap().unwrap();
+        assert_eq!(saved.play_mode, PlayMode::Random);
+        // Only what changed is written
+        assert!(!dir.path().join("config.json").exists());
+    }
+
+    #[test]
+    fn changes_are_saved_when_dropped() {
+        let dir = TempDir::new().unwrap();
+        let settings = settings(&dir);
+        let clone = settings.clone();
+
+        clone.modify_config(|config| config.tick_rate = 250);
+        assert_eq!(settings.config().tick_rate, 250);
+        drop(clone);
+        assert!(!dir.path().join("config.json").exists());
+
+        drop(settings);
+        let saved = fs::read_to_string(dir.path().join("config.json")).unwrap();
+        assert!(saved.contains("\"tick_rate\": 250"));
+    }
+
+    #[test]
+    fn edited_config_is_reloaded() {
+        let dir = TempDir::new().unwrap();
+        let settings = settings(&dir);
+        let config_file = dir.path().join("config.json");
+        assert!(!settings.reload().unwrap());
+
+        // Written from here
+        settings.modify_config(|config| config.tick_rate = 250);
+        settings.inner.lock().unwrap().save(|_| true);
+        assert!(!settings.reload().unwrap());
+
+        // Edited by someone else
+        thread::sleep(Duration::from_millis(10));
+        let config = Config {
+            extensions: vec![String::from("flac")],
+            ..settings.config()
+        };
+        config::write_config(&config_file, &config).unwrap();
+        assert!(settings.reload().unwrap());
+        assert_eq!(settings.config().extensions, vec![String::from("flac")]);
+
+        // Changed here meanwhile, kept and saved
+        settings.modify_config(|config| config.tick_rate = 100);
+        thread::sleep(Duration::from_millis(10));
+        let config = Config {
+            extensions: vec![String::from("mp3")],
+            ..config
+        };
+        config::write_config(&config_file, &config).unwrap();
+        assert!(settings.reload().unwrap());
+        assert_eq!(settings.config().extensions, vec![String::from("mp3")]);
+        assert_eq!(settings.config().tick_rate, 100);
+        settings.inner.lock().unwrap().save(|_| true);
+        assert_eq!(config::read_config(&config_file).unwrap().tick_rate, 100);
+        assert!(!settings.reload().unwrap());
+
+        thread::sleep(Duration::from_millis(10));
+        fs::write(&config_file, "{\"extensions\": [").unwrap();
+        assert!(matches!(
+            settings.reload(),
+            Err(config::ConfigError::Invalid { .. })
+        ));
+        assert_eq!(settings.config().extensions, vec![String::from("mp3")]);
+    }
+}
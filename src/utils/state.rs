@@ -0,0 +1,110 @@
+use super::config::{ConfigError, PlayMode, Sort};
+use dirs::{cache_dir, state_dir};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// What the player changes while it runs is saved here, so that the config
+// file only holds what the user chose. Settings saves it once changes settle
+// for SAVE_DELAY, and on exit.
+
+static STATE_PATH: &str = "dirplayer/state.json";
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct State {
+    pub current_file: String,
+    // Directory for current completions
+    pub working_directory: String,
+    pub play_mode: PlayMode,
+    pub sort: Sort,
+    // From 0 to 1
+    pub volume: f32,
+    // Times each file was started
+    pub play_counts: HashMap<String, u32>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            current_file: String::default(),
+            working_directory: String::default(),
+            play_mode: PlayMode::default(),
+            sort: Sort::default(),
+            volume: 1.0,
+            play_counts: HashMap::new(),
+        }
+    }
+}
+
+/// In the state directory, or in the cache directory where there is none.
+pub fn get_state_file() -> Result<PathBuf, ConfigError> {
+    let mut state_file = state_dir()
+        .or_else(cache_dir)
+        .ok_or(ConfigError::NoDirectory("state"))?;
+    state_file.push(STATE_PATH);
+    Ok(state_file)
+}
+
+/// The state at `path`, None when there is no file.
+pub fn read_state(path: &Path) -> Result<Option<State>, ConfigError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => {
+            serde_json::from_str(&contents)
+                .map(Some)
+                .map_err(|error| ConfigError::Invalid {
+                    path: path.to_path_buf(),
+                    backup: None,
+                    error: error.into(),
+                })
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(ConfigError::Io(path.to_path_buf(), e)),
+    }
+}
+
+pub fn write_state(path: &Path, state: &State) -> Result<(), ConfigError> {
+    let contents = serde_json::to_vec_pretty(state)
+        .map_err(|e| ConfigError::Io(path.to_path_buf(), e.into()))?;
+    super::write_atomic(path, &contents).map_err(|e| ConfigError::Io(path.to_path_buf(), e))
+}
+
+/// The saved state, the default one when it cannot be read, with the error logged.
+pub fn get_state() -> State {
+    let state = get_state_file().and_then(|path| read_state(&path));
+    match state {
+        Ok(state) => state.unwrap_or_default(),
+        Err(e) => {
+            log::error!("{e}");
+            State::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn state_is_written_whole() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("dirplayer/state.json");
+        assert_eq!(read_state(&path).unwrap(), None);
+
+        let state = State {
+            current_file: String::from("/music/a.mp3"),
+            working_directory: String::from("/music"),
+            play_mode: PlayMode::Random,
+            sort: Sort::Name,
+            volume: 0.5,
+            play_counts: HashMap::from([(String::from("/music/a.mp3"), 2)]),
+        };
+        write_state(&path, &state).unwrap();
+        assert_eq!(read_state(&path).unwrap(), Some(state));
+        // The temporary file was renamed
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+}
@@ -1,13 +1,15 @@
 use ratatui::{
-    style::{Color, Modifier, Style},
+    style::Style,
     text::{Line, Span},
 };
 use std::cmp::Ordering;
 
-pub fn string_to_styled_text(raw_string: &str, indices: &[usize]) -> Line<'static> {
-    let bold_style = Style::default()
-        .fg(Color::Green)
-        .add_modifier(Modifier::BOLD);
+/// Characters at `indices` in `bold_style`.
+pub fn string_to_styled_text(
+    raw_string: &str,
+    indices: &[usize],
+    bold_style: Style,
+) -> Line<'static> {
     let mut line = vec![];
     let mut indices = indices.to_owned();
 
@@ -26,11 +28,11 @@ pub fn string_to_styled_text(raw_string: &str, indices: &[usize]) -> Line<'stati
 /// Return the string in argument styled at indices.
 /// Return empty string when out of bound indices found.
 /// Return a word a spans
-pub fn string_to_styled_spans(raw_string: String, mut indices: Vec<usize>) -> Vec<Span<'static>> {
-    let bold_style = Style::default()
-        .fg(Color::Green)
-        .add_modifier(Modifier::BOLD);
-
+pub fn string_to_styled_spans(
+    raw_string: String,
+    mut indices: Vec<usize>,
+    bold_style: Style,
+) -> Vec<Span<'static>> {
     let mut styled_string: Vec<Span> = vec![];
     let mut start_index = 0_usize;
     let raw_string_len = raw_string.len();
@@ -72,27 +74,48 @@ pub fn string_to_styled_spans(raw_string: String, mut indices: Vec<usize>) -> Ve
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ratatui::style::{Color, Modifier};
 
     #[test]
     fn string_to_styled_spans_test() {
+        let bold_style = Style::default()
+            .fg(Color::Green)
+            .add_modifier(Modifier::BOLD);
+
         assert_eq!(
-            string_to_styled_string(&"aaaaaa".to_string(), &vec![1, 3, 4]),
-            "a\u{1b}[33m\u{1b}[0ma\u{1b}[33m\u{1b}[0m\u{1b}[33m\u{1b}[0ma"
+            string_to_styled_spans("aaaaaa".to_string(), vec![1, 3, 4], bold_style),
+            vec![
+                Span::from("a"),
+                Span::styled("a", bold_style),
+                Span::from("a"),
+                Span::styled("a", bold_style),
+                Span::from(""),
+                Span::styled("a", bold_style),
+                Span::from("a"),
+            ]
         );
 
         assert_eq!(
-            string_to_styled_string(&"bb".to_string(), &vec![1, 3, 4]),
-            ""
+            string_to_styled_spans("bb".to_string(), vec![1, 3, 4], bold_style),
+            vec![Span::from("")]
         );
 
         assert_eq!(
-            string_to_styled_string(&"cccccc".to_string(), &vec![]),
-            "cccccc"
+            string_to_styled_spans("cccccc".to_string(), vec![], bold_style),
+            vec![Span::from("cccccc")]
         );
 
         assert_eq!(
-            string_to_styled_string(&"dddddd".to_string(), &vec![4, 1, 3]),
-            "d\u{1b}[33m\u{1b}[0md\u{1b}[33m\u{1b}[0m\u{1b}[33m\u{1b}[0md"
+            string_to_styled_spans("dddddd".to_string(), vec![4, 1, 3], bold_style),
+            vec![
+                Span::from("d"),
+                Span::styled("d", bold_style),
+                Span::from("d"),
+                Span::styled("d", bold_style),
+                Span::from(""),
+                Span::styled("d", bold_style),
+                Span::from("d"),
+            ]
         );
     }
 }
@@ -0,0 +1,262 @@
+use super::archive::{self, ReadSeek};
+use anyhow::Result;
+use crossbeam_channel::{unbounded, Sender};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+
+// Tags are read from the headers of the file, before the container for ID3,
+// without decoding any sample. The tags of the files shown are read by a
+// thread, archive members may take a while to open.
+
+#[derive(Default, Clone, PartialEq, Debug)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    // As written, a number whose scale depends on the tagger
+    pub rating: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+/// Tags of a file or an archive member, empty when they cannot be read.
+pub fn read_tags(file_name: &str) -> Tags {
+    match try_read_tags(file_name) {
+        Ok(tags) => tags,
+        Err(e) => {
+            log::debug!("No tags for {file_name}: {e}");
+            Tags::default()
+        }
+    }
+}
+
+/// Modification time of the file when queued, and its tags, None while queued.
+type Entry = (SystemTime, Option<Tags>);
+
+/// Clones share the thread and the tags read.
+#[derive(Clone)]
+pub struct TagReader {
+    sender: Sender<String>,
+    tags: Arc<RwLock<HashMap<String, Entry>>>,
+}
+
+impl TagReader {
+    /// The thread stops once every clone is dropped.
+    pub fn start() -> TagReader {
+        let (sender, receiver) = unbounded::<String>();
+        let tags = Arc::new(RwLock::new(HashMap::new()));
+
+        let read = tags.clone();
+        thread::spawn(move || {
+            for file_name in receiver.iter() {
+                let file_tags = read_tags(&file_name);
+                // Unless they were forgotten meanwhile
+                if let Some((_, entry)) = read.write().unwrap().get_mut(&file_name) {
+                    *entry = Some(file_tags);
+                }
+            }
+        });
+
+        TagReader { sender, tags }
+    }
+
+    /// Queue `file_name`, last modified at `modified`, unless its tags are read or queued.
+    pub fn queue(&self, file_name: &str, modified: SystemTime) {
+        let mut tags = self.tags.write().unwrap();
+        if tags.contains_key(file_name) {
+            return;
+        }
+        tags.insert(file_name.to_string(), (modified, None));
+        if let Err(e) = self.sender.send(file_name.to_string()) {
+            log::error!("Could not queue {file_name}: {e}");
+        }
+    }
+
+    /// Tags of `file_name`, once read.
+    pub fn get(&self, file_name: &str) -> Option<Tags> {
+        let tags = self.tags.read().unwrap();
+        tags.get(file_name).and_then(|(_, tags)| tags.clone())
+    }
+
+    /// Forget the tags of the files gone, or modified since, as given by `modified`.
+    pub fn forget_changed(&self, modified: impl Fn(&str) -> Option<SystemTime>) {
+        let mut tags = self.tags.write().unwrap();
+        tags.retain(|file_name, (queued, _)| modified(file_name) == Some(*queued));
+    }
+}
+
+fn try_read_tags(file_name: &str) -> Result<Tags> {
+    let (source, path): (Box<dyn ReadSeek>, &str) = match archive::split_member(file_name) {
+        Some((archive, member)) => (archive::open_member(Path::new(archive), member)?, member),
+        None => (Box::new(BufReader::new(File::open(file_name)?)), file_name),
+    };
+    let mut hint = Hint::new();
+    if let Some(extension) = Path::new(path).extension() {
+        hint.with_extension(&extension.to_string_lossy());
+    }
+    let stream = MediaSourceStream::new(Box::new(Source(source)), Default::default());
+    let mut probed = symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    let mut tags = Tags::default();
+    // Found before the container, then in it
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        tags.add(revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.add(revision);
+    }
+    tags.duration = probed.format.default_track().and_then(|track| {
+        let params = &track.codec_params;
+        let time = params.time_base?.calc_time(params.n_frames?);
+        Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+    });
+    Ok(tags)
+}
+
+impl Tags {
+    fn add(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let field = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => &mut self.title,
+                Some(StandardTagKey::Artist) => &mut self.artist,
+                Some(StandardTagKey::Album) => &mut self.album,
+                Some(StandardTagKey::Rating) => &mut self.rating,
+                _ => continue,
+            };
+            // RIFF values keep their terminating NUL
+            let value = tag.value.to_string();
+            let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+            if !value.is_empty() {
+                *field = Some(value.to_string());
+            }
+        }
+    }
+}
+
+struct Source(Box<dyn ReadSeek>);
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Seek for Source {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl MediaSource for Source {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((data.len() as u32).to_le_bytes());
+        chunk.extend(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    /// One second of 8 kHz mono silence, with an INFO list.
+    fn wav_with_info(info: &[(&[u8], &str)]) -> Vec<u8> {
+        let mut fmt = vec![];
+        fmt.extend(1u16.to_le_bytes());
+        fmt.extend(1u16.to_le_bytes());
+        fmt.extend(8000u32.to_le_bytes());
+        fmt.extend(16000u32.to_le_bytes());
+        fmt.extend(2u16.to_le_bytes());
+        fmt.extend(16u16.to_le_bytes());
+
+        let mut list = b"INFO".to_vec();
+        for (id, value) in info {
+            list.extend(chunk(id, format!("{value}\0").as_bytes()));
+        }
+
+        let mut wave = b"WAVE".to_vec();
+        wave.extend(chunk(b"fmt ", &fmt));
+        wave.extend(chunk(b"LIST", &list));
+        wave.extend(chunk(b"data", &[0; 16000]));
+        chunk(b"RIFF", &wave)
+    }
+
+    #[test]
+    fn tags_and_duration_are_read() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("song.wav");
+        std::fs::write(
+            &path,
+            wav_with_info(&[(b"INAM", "Title"), (b"IART", "Artist"), (b"IPRD", "")]),
+        )
+        .unwrap();
+
+        let tags = read_tags(path.to_str().unwrap());
+        assert_eq!(
+            tags,
+            Tags {
+                title: Some(String::from("Title")),
+                artist: Some(String::from("Artist")),
+                album: None,
+                rating: None,
+                duration: Some(Duration::from_secs(1)),
+            }
+        );
+
+        std::fs::write(&path, b"not a song").unwrap();
+        assert_eq!(read_tags(path.to_str().unwrap()), Tags::default());
+    }
+
+    #[test]
+    fn queued_tags_are_read_by_the_thread() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("song.wav");
+        std::fs::write(&path, wav_with_info(&[(b"INAM", "Title")])).unwrap();
+        let file_name = path.to_str().unwrap();
+
+        let reader = TagReader::start();
+        assert_eq!(reader.get(file_name), None);
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        reader.queue(file_name, modified);
+        let start = std::time::Instant::now();
+        while reader.get(file_name).is_none() {
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            reader.get(file_name).unwrap().title.as_deref(),
+            Some("Title")
+        );
+
+        reader.forget_changed(|_| Some(modified));
+        assert!(reader.get(file_name).is_some());
+        reader.forget_changed(|_| Some(modified + Duration::from_secs(1)));
+        assert_eq!(reader.get(file_name), None);
+    }
+}